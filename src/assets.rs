@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use piston_window::*;
+use crate::clock::*;
+use crate::game::GameWindow;
+
+pub const ATLAS_MAGIC: &str = "digger-atlas";
+
+/// Every image the game draws.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Sprite {
+    Background,
+    Wall,
+    Emerald,
+    Bag,
+    Bonus,
+    FallingBag,
+    BrokenBag,
+    PlayerLeft,
+    PlayerRight,
+    PlayerUp,
+    PlayerDown,
+    Death,
+    Monster,
+    Hobbin,
+    Shot,
+}
+
+impl Sprite {
+    pub const ALL: [Sprite; 15] = [
+        Sprite::Background,
+        Sprite::Wall,
+        Sprite::Emerald,
+        Sprite::Bag,
+        Sprite::Bonus,
+        Sprite::FallingBag,
+        Sprite::BrokenBag,
+        Sprite::PlayerLeft,
+        Sprite::PlayerRight,
+        Sprite::PlayerUp,
+        Sprite::PlayerDown,
+        Sprite::Death,
+        Sprite::Monster,
+        Sprite::Hobbin,
+        Sprite::Shot,
+    ];
+
+    /// The name the sprite goes by in the atlas file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Sprite::Background => "background",
+            Sprite::Wall => "wall",
+            Sprite::Emerald => "emerald",
+            Sprite::Bag => "bag",
+            Sprite::Bonus => "bonus",
+            Sprite::FallingBag => "falling_bag",
+            Sprite::BrokenBag => "broken_bag",
+            Sprite::PlayerLeft => "player_left",
+            Sprite::PlayerRight => "player_right",
+            Sprite::PlayerUp => "player_up",
+            Sprite::PlayerDown => "player_down",
+            Sprite::Death => "death",
+            Sprite::Monster => "monster",
+            Sprite::Hobbin => "hobbin",
+            Sprite::Shot => "shot",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AtlasError {
+    Io { path: String, error: io::Error },
+    Malformed { line: usize },
+    UnknownSprite { line: usize, name: String },
+    MissingSprite(Sprite),
+}
+
+impl fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AtlasError::Io { path, error } => write!(f, "cannot read atlas {}: {}", path, error),
+            AtlasError::Malformed { line } => write!(f, "atlas line {}: expected `name = image x,y,w,h ...`", line),
+            AtlasError::UnknownSprite { line, name } => write!(f, "atlas line {}: unknown sprite `{}`", line, name),
+            AtlasError::MissingSprite(sprite) => write!(f, "atlas has no entry for `{}`", sprite.name()),
+        }
+    }
+}
+
+/// Where a sprite's animation frames live: one image and a source rectangle per frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frames {
+    pub image: String,
+    pub rects: Vec<[f64; 4]>,
+}
+
+/// The sprite sheet description: a `digger-atlas 1` header, an optional `fps = N` line
+/// giving the animation rate, then `name = image x,y,w,h [x,y,w,h ...]` per sprite.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Atlas {
+    pub fps: u64,
+    pub sprites: HashMap<Sprite, Frames>,
+}
+
+impl Atlas {
+    pub fn load(path: &str) -> Result<Self, AtlasError> {
+        let source = fs::read_to_string(path).map_err(|error| AtlasError::Io {
+            path: path.to_string(),
+            error,
+        })?;
+        Atlas::parse(&source)
+    }
+
+    pub fn parse(input: &str) -> Result<Self, AtlasError> {
+        let mut atlas = Atlas {
+            fps: 0,
+            sprites: HashMap::<Sprite, Frames>::new(),
+        };
+        let mut header = false;
+        for (number, line) in input.lines().enumerate() {
+            let malformed = AtlasError::Malformed { line: number + 1 };
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with(';') {
+                continue;
+            }
+            if !header {
+                if trimmed != format!("{} 1", ATLAS_MAGIC) {
+                    return Err(malformed);
+                }
+                header = true;
+                continue;
+            }
+            let (name, value) = match trimmed.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => return Err(malformed),
+            };
+            if name == "fps" {
+                atlas.fps = value.parse().map_err(|_| malformed)?;
+                continue;
+            }
+            let sprite = match Sprite::ALL.iter().find(|sprite| sprite.name() == name) {
+                Some(x) => *x,
+                None => return Err(AtlasError::UnknownSprite { line: number + 1, name: name.to_string() }),
+            };
+            let mut words = value.split_whitespace();
+            let image = match words.next() {
+                Some(x) => x.to_string(),
+                None => return Err(malformed),
+            };
+            let mut rects = Vec::<[f64; 4]>::new();
+            for word in words {
+                let numbers = word.split(',').map(|n| n.parse::<f64>()).collect::<Result<Vec<f64>, _>>();
+                match numbers.as_deref() {
+                    Ok(&[x, y, w, h]) => rects.push([x, y, w, h]),
+                    _ => return Err(malformed),
+                }
+            }
+            if rects.is_empty() {
+                return Err(malformed);
+            }
+            atlas.sprites.insert(sprite, Frames { image, rects });
+        }
+        if !header {
+            return Err(AtlasError::Malformed { line: 1 });
+        }
+        match Sprite::ALL.iter().find(|sprite| !atlas.sprites.contains_key(sprite)) {
+            Some(sprite) => Err(AtlasError::MissingSprite(*sprite)),
+            None => Ok(atlas),
+        }
+    }
+
+    /// Source rectangle of the frame of `sprite` shown at `tick`.
+    pub fn frame(&self, sprite: Sprite, tick: u64) -> [f64; 4] {
+        let rects = &self.sprites[&sprite].rects;
+        rects[(tick * self.fps / TICKS_PER_SECOND) as usize % rects.len()]
+    }
+}
+
+/// Textures loaded once at startup and shared by every object drawn with them.
+pub struct Assets {
+    pub atlas: Atlas,
+    textures: HashMap<String, G2dTexture>,
+}
+
+impl Assets {
+    pub fn load(win: &mut GameWindow, atlas: Atlas) -> Self {
+        let mut context = win.create_texture_context();
+        let mut textures = HashMap::<String, G2dTexture>::new();
+        for frames in atlas.sprites.values() {
+            if textures.contains_key(&frames.image) {
+                continue;
+            }
+            let texture = Texture::from_path(&mut context, &frames.image, Flip::None, &TextureSettings::new())
+                .unwrap_or_else(|e| panic!("cannot load {}: {}", frames.image, e));
+            textures.insert(frames.image.clone(), texture);
+        }
+        Assets { atlas, textures }
+    }
+
+    /// Draws the frame of `sprite` due at `tick` with its top-left corner at (`left`, `top`).
+    pub fn draw(&self, sprite: Sprite, tick: u64, left: f64, top: f64, c: &Context, g: &mut G2d) {
+        let texture = &self.textures[&self.atlas.sprites[&sprite].image];
+        Image::new()
+            .src_rect(self.atlas.frame(sprite, tick))
+            .draw(texture, &c.draw_state, c.transform.trans(left, top), g);
+    }
+}
@@ -0,0 +1,131 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::level::*;
+use crate::world::*;
+
+pub const CAMPAIGN_MAGIC: &str = "digger-campaign";
+
+#[derive(Debug)]
+pub enum CampaignError {
+    Io { path: String, error: io::Error },
+    Malformed { path: String, line: usize },
+    Empty { path: String },
+}
+
+impl fmt::Display for CampaignError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CampaignError::Io { path, error } => write!(f, "cannot read campaign {}: {}", path, error),
+            CampaignError::Malformed { path, line } => write!(f, "{}:{}: malformed campaign line", path, line),
+            CampaignError::Empty { path } => write!(f, "campaign {} lists no levels", path),
+        }
+    }
+}
+
+/// An ordered list of level files played one after another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Campaign {
+    pub name: String,
+    pub levels: Vec<String>,
+    current: usize,
+}
+
+impl Campaign {
+    /// A campaign made of just one map.
+    pub fn single(path: &str) -> Self {
+        Campaign {
+            name: String::new(),
+            levels: vec![path.to_string()],
+            current: 0,
+        }
+    }
+
+    /// Reads a manifest: a `digger-campaign 1` header, an optional `name = ...` line,
+    /// then one level path per line, relative to the manifest itself.
+    pub fn load(path: &str) -> Result<Self, CampaignError> {
+        let source = fs::read_to_string(path).map_err(|error| CampaignError::Io {
+            path: path.to_string(),
+            error,
+        })?;
+        let base = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        Campaign::parse(&source, base).map_err(|e| match e {
+            CampaignError::Malformed { line, .. } => CampaignError::Malformed { path: path.to_string(), line },
+            CampaignError::Empty { .. } => CampaignError::Empty { path: path.to_string() },
+            e => e,
+        })
+    }
+
+    /// Parses a manifest whose level paths are relative to `base`.
+    pub fn parse(input: &str, base: &Path) -> Result<Self, CampaignError> {
+        let malformed = |number: usize| CampaignError::Malformed { path: String::new(), line: number + 1 };
+        let mut campaign = Campaign {
+            name: String::new(),
+            levels: Vec::<String>::new(),
+            current: 0,
+        };
+        let mut header = false;
+        for (number, line) in input.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with(';') {
+                continue;
+            }
+            if !header {
+                if trimmed != format!("{} 1", CAMPAIGN_MAGIC) {
+                    return Err(malformed(number));
+                }
+                header = true;
+                continue;
+            }
+            if let Some(name) = trimmed.strip_prefix("name") {
+                match name.trim_start().strip_prefix('=') {
+                    Some(name) => campaign.name = name.trim().to_string(),
+                    None => return Err(malformed(number)),
+                }
+                continue;
+            }
+            campaign.levels.push(base.join(trimmed).to_string_lossy().into_owned());
+        }
+        if campaign.levels.is_empty() {
+            return Err(CampaignError::Empty { path: String::new() });
+        }
+        Ok(campaign)
+    }
+
+    pub fn current_path(&self) -> &str {
+        &self.levels[self.current]
+    }
+
+    /// 1-based number of the level being played.
+    pub fn level_number(&self) -> usize {
+        self.current + 1
+    }
+
+    pub fn is_last(&self) -> bool {
+        self.current + 1 >= self.levels.len()
+    }
+
+    /// Moves on to the next level. Returns false once the campaign is complete.
+    pub fn advance(&mut self) -> bool {
+        if self.is_last() {
+            return false;
+        }
+        self.current += 1;
+        true
+    }
+
+    /// Goes back to the first level.
+    pub fn restart(&mut self) {
+        self.current = 0;
+    }
+
+    /// Builds the world for the current level, starting from `progress` if this is not the first one.
+    pub fn load_level(&self, progress: Option<&[Progress]>, seed: u64) -> Result<World, MapError> {
+        let mut world = World::from_level(&Level::load(self.current_path())?, seed);
+        if let Some(progress) = progress {
+            world.carry(progress);
+        }
+        Ok(world)
+    }
+}
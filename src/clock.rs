@@ -0,0 +1,77 @@
+use std::time::Instant;
+
+pub const TICKS_PER_SECOND: u64 = 18;
+
+/// Source of fixed simulation ticks. The world only ever advances in whole ticks,
+/// so the rules behave the same no matter how fast frames are rendered.
+pub trait Clock {
+    /// Number of ticks that became due since the previous call.
+    fn ticks_due(&mut self) -> u64;
+}
+
+/// Ticks driven by wall-clock time.
+pub struct RealClock {
+    start: Instant,
+    ticks_done: u64,
+}
+
+impl Default for RealClock {
+    fn default() -> Self {
+        RealClock::new()
+    }
+}
+
+impl RealClock {
+    pub fn new() -> Self {
+        RealClock {
+            start: Instant::now(),
+            ticks_done: 0,
+        }
+    }
+}
+
+impl Clock for RealClock {
+    fn ticks_due(&mut self) -> u64 {
+        let elapsed = self.start.elapsed();
+        let total = elapsed.as_secs() * TICKS_PER_SECOND
+            + u64::from(elapsed.subsec_nanos()) * TICKS_PER_SECOND / 1_000_000_000;
+        let due = total - self.ticks_done;
+        self.ticks_done = total;
+        due
+    }
+}
+
+/// Ticks handed out only when asked to, for tests.
+#[cfg(test)]
+pub struct ManualClock {
+    pending: u64,
+}
+
+#[cfg(test)]
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new() -> Self {
+        ManualClock {
+            pending: 0,
+        }
+    }
+
+    pub fn advance(&mut self, ticks: u64) {
+        self.pending += ticks;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn ticks_due(&mut self) -> u64 {
+        let due = self.pending;
+        self.pending = 0;
+        due
+    }
+}
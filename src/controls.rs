@@ -0,0 +1,415 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use piston_window::*;
+use crate::level::MAX_PLAYERS;
+use crate::movable_objects::*;
+use crate::world::*;
+
+pub const CONTROLS_MAGIC: &str = "digger-controls";
+/// How far an analog stick has to be pushed before it counts, out of 1.
+pub const DEFAULT_DEAD_ZONE: f64 = 0.35;
+
+/// Everything a player can ask the game to do, independent of the key bound to it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Fire,
+    Pause,
+    /// Starts the current level over.
+    Restart,
+    Quit,
+    /// Gets past the title, level complete and game over screens.
+    Confirm,
+}
+
+impl Action {
+    pub const ALL: [Action; 9] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Fire,
+        Action::Pause,
+        Action::Restart,
+        Action::Quit,
+        Action::Confirm,
+    ];
+
+    /// The name the action goes by in the controls file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveUp => "move_up",
+            Action::MoveDown => "move_down",
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::Fire => "fire",
+            Action::Pause => "pause",
+            Action::Restart => "restart",
+            Action::Quit => "quit",
+            Action::Confirm => "confirm",
+        }
+    }
+
+    /// The command the action sends to the world, if it is played rather than handled by the game.
+    pub fn command(self) -> Option<Command> {
+        match self {
+            Action::MoveUp => Some(Command::Up),
+            Action::MoveDown => Some(Command::Down),
+            Action::MoveLeft => Some(Command::Left),
+            Action::MoveRight => Some(Command::Right),
+            Action::Fire => Some(Command::Fire),
+            _ => None,
+        }
+    }
+
+    fn is_move(self) -> bool {
+        matches!(self, Action::MoveUp | Action::MoveDown | Action::MoveLeft | Action::MoveRight)
+    }
+}
+
+#[derive(Debug)]
+pub enum ControlsError {
+    Io { path: String, error: io::Error },
+    Malformed { line: usize },
+    UnknownAction { line: usize, name: String },
+    UnknownKey { line: usize, name: String },
+}
+
+impl fmt::Display for ControlsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ControlsError::Io { path, error } => write!(f, "cannot read controls {}: {}", path, error),
+            ControlsError::Malformed { line } => write!(f, "controls line {}: expected `action = key [key ...]`, `dead_zone = N` or `[player N]`", line),
+            ControlsError::UnknownAction { line, name } => write!(f, "controls line {}: unknown action `{}`", line, name),
+            ControlsError::UnknownKey { line, name } => write!(f, "controls line {}: unknown key `{}`", line, name),
+        }
+    }
+}
+
+/// Something on a keyboard or gamepad that can be bound to an action.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Trigger {
+    Key(Key),
+    /// A gamepad button by number, `pad0` in the controls file.
+    Pad(u8),
+    /// An analog stick axis pushed past the dead-zone, `axis1-` or `axis1+`.
+    Axis(u8, bool),
+    /// A D-pad direction, `dpad_up` and so on.
+    DPad(Direction),
+}
+
+impl Trigger {
+    /// The name the trigger goes by in the controls file.
+    pub fn name(self) -> String {
+        match self {
+            Trigger::Key(key) => format!("{:?}", key),
+            Trigger::Pad(button) => format!("pad{}", button),
+            Trigger::Axis(axis, positive) => format!("axis{}{}", axis, if positive { '+' } else { '-' }),
+            Trigger::DPad(dir) => format!("dpad_{:?}", dir).to_lowercase(),
+        }
+    }
+
+    fn parse(name: &str) -> Option<Trigger> {
+        let dpad = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+        if let Some(dir) = dpad.iter().find(|dir| Trigger::DPad(**dir).name() == name) {
+            return Some(Trigger::DPad(*dir));
+        }
+        if let Some(number) = name.strip_prefix("pad") {
+            return number.parse().ok().map(Trigger::Pad);
+        }
+        if let Some(axis) = name.strip_prefix("axis") {
+            return match axis.split_at(axis.len().saturating_sub(1)) {
+                (number, "+") => number.parse().ok().map(|number| Trigger::Axis(number, true)),
+                (number, "-") => number.parse().ok().map(|number| Trigger::Axis(number, false)),
+                _ => None,
+            };
+        }
+        parse_key(name).map(Trigger::Key)
+    }
+}
+
+/// Which triggers fire which action. Any number of triggers can share an action, and a trigger can fire several.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Controls {
+    bindings: Vec<(Trigger, Action)>,
+    pub dead_zone: f64,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        Controls {
+            bindings: vec![
+                (Trigger::Key(Key::W), Action::MoveUp),
+                (Trigger::Key(Key::Up), Action::MoveUp),
+                (Trigger::DPad(Direction::Up), Action::MoveUp),
+                (Trigger::Axis(1, false), Action::MoveUp),
+                (Trigger::Key(Key::S), Action::MoveDown),
+                (Trigger::Key(Key::Down), Action::MoveDown),
+                (Trigger::DPad(Direction::Down), Action::MoveDown),
+                (Trigger::Axis(1, true), Action::MoveDown),
+                (Trigger::Key(Key::A), Action::MoveLeft),
+                (Trigger::Key(Key::Left), Action::MoveLeft),
+                (Trigger::DPad(Direction::Left), Action::MoveLeft),
+                (Trigger::Axis(0, false), Action::MoveLeft),
+                (Trigger::Key(Key::D), Action::MoveRight),
+                (Trigger::Key(Key::Right), Action::MoveRight),
+                (Trigger::DPad(Direction::Right), Action::MoveRight),
+                (Trigger::Axis(0, true), Action::MoveRight),
+                (Trigger::Key(Key::Space), Action::Fire),
+                (Trigger::Pad(0), Action::Fire),
+                (Trigger::Key(Key::P), Action::Pause),
+                (Trigger::Pad(6), Action::Pause),
+                (Trigger::Key(Key::R), Action::Restart),
+                (Trigger::Pad(4), Action::Restart),
+                (Trigger::Key(Key::Escape), Action::Quit),
+                (Trigger::Key(Key::Return), Action::Confirm),
+                (Trigger::Pad(0), Action::Confirm),
+            ],
+            dead_zone: DEFAULT_DEAD_ZONE,
+        }
+    }
+}
+
+impl Controls {
+    /// Default controls of `player`. The second player moves with I, J, K and L and fires with O;
+    /// both keep the gamepad bindings, which only answer to their own gamepad.
+    pub fn for_player(player: usize) -> Self {
+        let mut controls = Controls::default();
+        if player > 0 {
+            controls.bindings.retain(|(trigger, _)| !matches!(trigger, Trigger::Key(_)));
+            controls.bindings.extend([
+                (Trigger::Key(Key::I), Action::MoveUp),
+                (Trigger::Key(Key::K), Action::MoveDown),
+                (Trigger::Key(Key::J), Action::MoveLeft),
+                (Trigger::Key(Key::L), Action::MoveRight),
+                (Trigger::Key(Key::O), Action::Fire),
+            ]);
+        }
+        controls
+    }
+
+    /// Reads the controls of `player` from the file at `path`, falling back to the defaults if there is none.
+    pub fn load(path: &str, player: usize) -> Result<Self, ControlsError> {
+        match fs::read_to_string(path) {
+            Ok(source) => Controls::parse(&source, player),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Controls::for_player(player)),
+            Err(error) => Err(ControlsError::Io { path: path.to_string(), error }),
+        }
+    }
+
+    /// Parses a `digger-controls 1` header, an optional `dead_zone = N` line and `action = key [key ...]` lines
+    /// for `player`. Lines up to the first `[player N]` heading are for the first player.
+    /// An action listed in the file loses its default keys, the others keep them.
+    pub fn parse(input: &str, player: usize) -> Result<Self, ControlsError> {
+        let mut controls = Controls::for_player(player);
+        let mut header = false;
+        let mut section = 0;
+        for (number, line) in input.lines().enumerate() {
+            let malformed = ControlsError::Malformed { line: number + 1 };
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with(';') {
+                continue;
+            }
+            if !header {
+                if trimmed != format!("{} 1", CONTROLS_MAGIC) {
+                    return Err(malformed);
+                }
+                header = true;
+                continue;
+            }
+            if let Some(heading) = trimmed.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                section = match heading.strip_prefix("player ").map(|number| number.trim().parse::<usize>()) {
+                    Some(Ok(number)) if (1..=MAX_PLAYERS).contains(&number) => number - 1,
+                    _ => return Err(malformed),
+                };
+                continue;
+            }
+            let (name, value) = match trimmed.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => return Err(malformed),
+            };
+            if name == "dead_zone" {
+                let dead_zone = match value.parse::<f64>() {
+                    Ok(x) if (0.0..1.0).contains(&x) => x,
+                    _ => return Err(malformed),
+                };
+                if section == player {
+                    controls.dead_zone = dead_zone;
+                }
+                continue;
+            }
+            let action = match Action::ALL.iter().find(|action| action.name() == name) {
+                Some(x) => *x,
+                None => return Err(ControlsError::UnknownAction { line: number + 1, name: name.to_string() }),
+            };
+            let mut triggers = Vec::<Trigger>::new();
+            for word in value.split_whitespace() {
+                match Trigger::parse(word) {
+                    Some(trigger) => triggers.push(trigger),
+                    None => return Err(ControlsError::UnknownKey { line: number + 1, name: word.to_string() }),
+                }
+            }
+            if section == player {
+                controls.bindings.retain(|(_, bound)| *bound != action);
+                controls.bindings.extend(triggers.into_iter().map(|trigger| (trigger, action)));
+            }
+        }
+        if !header {
+            return Err(ControlsError::Malformed { line: 1 });
+        }
+        Ok(controls)
+    }
+
+    /// Every action bound to `trigger`, in the order they were bound.
+    pub fn actions(&self, trigger: Trigger) -> Vec<Action> {
+        self.bindings.iter().filter(|(bound, _)| *bound == trigger).map(|(_, action)| *action).collect()
+    }
+
+    /// Name of the first trigger bound to `action`, for on-screen hints.
+    pub fn key_name(&self, action: Action) -> String {
+        match self.bindings.iter().find(|(_, bound)| *bound == action) {
+            Some((trigger, _)) => trigger.name(),
+            None => "nothing".to_string(),
+        }
+    }
+}
+
+/// Looks a key up by the name piston gives it, such as `W`, `Space` or `Left`, ignoring case.
+fn parse_key(name: &str) -> Option<Key> {
+    (0..0x80).chain(0x4000_0039..=0x4000_011A)
+        .map(Key::from)
+        .find(|key| *key != Key::Unknown && format!("{:?}", key).eq_ignore_ascii_case(name))
+}
+
+/// Turns keyboard and gamepad events into actions, tracking held directions so letting go of one
+/// stops the player or falls back to another one still held.
+pub struct PlayerInput {
+    pub controls: Controls,
+    /// The gamepad this player uses; events from other gamepads are left to the other players.
+    pad: u32,
+    held: Vec<Action>,
+    /// Axes pushed past the dead-zone, and the way they are pushed.
+    axes: Vec<(u8, bool)>,
+    /// D-pad directions down.
+    dpad: Vec<Direction>,
+    commands: Vec<Command>,
+}
+
+impl PlayerInput {
+    pub fn new(controls: Controls, pad: u32) -> Self {
+        PlayerInput {
+            controls,
+            pad,
+            held: Vec::<Action>::new(),
+            axes: Vec::<(u8, bool)>::new(),
+            dpad: Vec::<Direction>::new(),
+            commands: Vec::<Command>::new(),
+        }
+    }
+
+    /// Handles one event. Commands for the world are kept until `take_commands`;
+    /// the other actions pressed are returned for the game to handle.
+    pub fn event<E: GenericEvent>(&mut self, e: &E) -> Vec<Action> {
+        let mut actions = Vec::<Action>::new();
+        let pad = self.pad;
+        match e.press_args() {
+            Some(Button::Keyboard(key)) => self.press(Trigger::Key(key), &mut actions),
+            Some(Button::Controller(button)) if button.id == pad => self.press(Trigger::Pad(button.button), &mut actions),
+            Some(Button::Hat(hat)) if hat.id == pad => self.dpad(hat.state, &mut actions),
+            _ => {},
+        }
+        match e.release_args() {
+            Some(Button::Keyboard(key)) => self.release(Trigger::Key(key)),
+            Some(Button::Controller(button)) if button.id == pad => self.release(Trigger::Pad(button.button)),
+            Some(Button::Hat(hat)) if hat.id == pad => self.dpad(HatState::Centered, &mut actions),
+            _ => {},
+        }
+        if let Some(args) = e.controller_axis_args().filter(|args| args.id == pad) {
+            self.axis(args, &mut actions);
+        }
+        actions
+    }
+
+    /// Presses and releases the axis direction that moved in or out of the dead-zone.
+    fn axis(&mut self, args: ControllerAxisArgs, actions: &mut Vec<Action>) {
+        let pushed = if args.position > self.controls.dead_zone {
+            Some(true)
+        } else if args.position < -self.controls.dead_zone {
+            Some(false)
+        } else {
+            None
+        };
+        let before = self.axes.iter().find(|(axis, _)| *axis == args.axis).map(|(_, positive)| *positive);
+        if before == pushed {
+            return;
+        }
+        if let Some(positive) = before {
+            self.axes.retain(|(axis, _)| *axis != args.axis);
+            self.release(Trigger::Axis(args.axis, positive));
+        }
+        if let Some(positive) = pushed {
+            self.axes.push((args.axis, positive));
+            self.press(Trigger::Axis(args.axis, positive), actions);
+        }
+    }
+
+    /// Moves the D-pad to `state`; diagonals hold both directions.
+    fn dpad(&mut self, state: HatState, actions: &mut Vec<Action>) {
+        let down = match state {
+            HatState::Centered => vec![],
+            HatState::Up => vec![Direction::Up],
+            HatState::Down => vec![Direction::Down],
+            HatState::Left => vec![Direction::Left],
+            HatState::Right => vec![Direction::Right],
+            HatState::RightUp => vec![Direction::Right, Direction::Up],
+            HatState::RightDown => vec![Direction::Right, Direction::Down],
+            HatState::LeftUp => vec![Direction::Left, Direction::Up],
+            HatState::LeftDown => vec![Direction::Left, Direction::Down],
+        };
+        for dir in std::mem::take(&mut self.dpad) {
+            if !down.contains(&dir) {
+                self.release(Trigger::DPad(dir));
+            } else {
+                self.dpad.push(dir);
+            }
+        }
+        for dir in down {
+            if !self.dpad.contains(&dir) {
+                self.dpad.push(dir);
+                self.press(Trigger::DPad(dir), actions);
+            }
+        }
+    }
+
+    fn press(&mut self, trigger: Trigger, actions: &mut Vec<Action>) {
+        for action in self.controls.actions(trigger) {
+            if action.is_move() {
+                self.held.retain(|held| *held != action);
+                self.held.push(action);
+            }
+            match action.command() {
+                Some(command) => self.commands.push(command),
+                None => actions.push(action),
+            }
+        }
+    }
+
+    fn release(&mut self, trigger: Trigger) {
+        for action in self.controls.actions(trigger) {
+            if !self.held.contains(&action) {
+                continue;
+            }
+            self.held.retain(|held| *held != action);
+            let command = self.held.last().and_then(|held| held.command()).unwrap_or(Command::Stop);
+            self.commands.push(command);
+        }
+    }
+
+    pub fn take_commands(&mut self) -> Vec<Command> {
+        std::mem::take(&mut self.commands)
+    }
+}
@@ -1,756 +1,304 @@
 
-use std::collections::VecDeque;
 use piston_window::*;
+use crate::assets::*;
+use crate::controls::*;
 use crate::immovable_objects::*;
 use crate::movable_objects::*;
-use std::fs::File;
-use std::io::BufReader;
-use std::io::BufRead;
-use std::time::{Duration, Instant};
-
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub enum RuntimeError {
-    InvalidFile,
-    PlayerDead,
-    Victory,
-}
+use crate::objective::*;
+use crate::level::*;
+use crate::world::*;
+use crate::clock::*;
+use crate::replay::*;
+use crate::session::*;
 
 const OPENGL: OpenGL = OpenGL::V3_2;
-pub const BLOCK_SIZE: usize = 30;
 const MAX_FPS: u64 = 18;
-pub const PLAYER_SPEED: f64 = BLOCK_SIZE as f64;
-pub const MONSTER_SPEED: f64 = 0.5;
-pub const BAG_SPEED: f64 = 0.5;
-pub const SHOT_SPEED: f64 = 0.8;
-const MAX_MONSTERS: usize = 3;
-const SCORE_PER_EMERALD: usize = 20;
-const BACKGROUND_PATH: &str = "background.png";
-const PLAYER_PATH: &str = "right.png";
-const BAG_PATH: &str = "bag.png";
-const BROKEN_BAG_PATH: &str = "broken.png";
-const MONSTER_PATH: &str = "monster.png";
-const FALLING_BAG_PATH: &str = "falling.png";
-const EMERALD_PATH: &str = "emerald.png";
-const DEATH_PATH: &str = "rip.png";
-const SHOT_PATH: &str = "shot.png";
-const WALL_PATH: &str = "wall.png";
-const TIME_TO_FALL: u64 = 3;
-const TIME_TO_SPAWN: u64 = 3;
-
+const FONT_PATH: &str = "DejaVuSansMono-Bold.ttf";
+const FONT_SIZE: u32 = 20;
+const LINE_HEIGHT: f64 = 30.0;
+const HUD_FONT_SIZE: u32 = 14;
+const HUD_LINE_HEIGHT: f64 = 20.0;
+/// SDL2 backs the window because it reports gamepads, which glutin does not.
+#[cfg(feature = "sdl2")]
+pub type GameWindow = PistonWindow<sdl2_window::Sdl2Window>;
+/// Keyboard only, for machines without the SDL2 library.
+#[cfg(all(feature = "glutin", not(feature = "sdl2")))]
+pub type GameWindow = PistonWindow<glutin_window::GlutinWindow>;
+#[cfg(not(any(feature = "sdl2", feature = "glutin")))]
+compile_error!("enable the `sdl2` or the `glutin` feature to pick a window backend");
+/// Height of the status strip drawn under the grid.
+const HUD_HEIGHT: f64 = 2.0 * HUD_LINE_HEIGHT + 10.0;
+
+/// Piston front-end: owns the window and draws whatever state `session` is in.
 pub struct Game {
-    pub immovable: Vec<Vec<Immovable>>,
-    pub immovable_texture: Vec<Vec<G2dTexture>>,
-    pub movable: Vec<Movable>,
-    pub movable_texture: Vec<G2dTexture>,
-    line_len: usize,
-    num_lines: usize,
-    win: PistonWindow,
-    spawn_x: f64,
-    spawn_y: f64,
-    previous: Vec<i32>,
-    max_score: usize,
-    current_score: usize,
-    shots: usize,
-    last_spawned: std::time::Instant,
-    current_number_monsters: usize,
+    pub session: Session,
+    clock: Box<dyn Clock>,
+    playback: Option<Replay>,
+    recording: Option<Replay>,
+    /// Set once the recorded or replayed run is over; later runs are neither replayed nor recorded.
+    replay_done: bool,
+    /// One per player, whether or not the level has a cell for them.
+    inputs: Vec<PlayerInput>,
+    glyphs: Glyphs,
+    assets: Assets,
+    win: GameWindow,
 }
 
 impl Game {
-    pub fn new() -> Self {
-        Game {
-            immovable: Vec::<Vec<Immovable>>::new(),
-            immovable_texture: Vec::<Vec<G2dTexture>>::new(),
-            movable: Vec::<Movable>::new(),
-            movable_texture: Vec::<G2dTexture>::new(),
-            line_len: 0,
-            num_lines: 0,
-            win: WindowSettings::new("DIGGER", [10, 10])
-            .exit_on_esc(true)
+    pub fn new(session: Session, atlas: Atlas, controls: Vec<Controls>, clock: Box<dyn Clock>) -> Self {
+        let world = &session.world;
+        let mut win: GameWindow = WindowSettings::new(world.name.clone(), [10, 10])
+            .exit_on_esc(false)
+            .controllers(true)
             .graphics_api(OPENGL)
             .build()
-            .unwrap(),
-            spawn_x: 0.0,
-            spawn_y: 0.0,
-            previous: Vec::<i32>::new(),
-            max_score: 0,
-            current_score: 0,
-            shots: 0,
-            last_spawned: Instant::now(),
-            current_number_monsters: 0,
-        }
-    }
+            .unwrap();
+        win.set_size(Game::window_size(world));
+        win.set_max_fps(MAX_FPS);
+        win.set_ups(TICKS_PER_SECOND);
+        let glyphs = win.load_font(FONT_PATH).unwrap();
+        let assets = Assets::load(&mut win, atlas);
 
-    pub fn initialize(&mut self, path: &str) {
-        let file = File::open(path);
-        let f;
-        match file {
-            Ok(x) => {
-                f = BufReader::new(x);
-            }
-            Err(e) => {
-                println!("{:?}", e);
-                return
-            },
-        }
-        for line in f.lines() {
-            let unwrapped = line.unwrap();
-            let splitted = Game::split_row(&unwrapped);
-            let splitted_as_vec;
-            match splitted {
-                Some(x) => splitted_as_vec = x,
-                None => return,
-            }
-            
-            let added = self.add_row(splitted_as_vec);
-            match added {
-                Ok(()) => {},
-                Err(e) => {
-                    println!("{:?}", e);
-                    return;
-                }
-            }
-            self.num_lines += 1;
+        Game {
+            session,
+            clock,
+            playback: None,
+            recording: None,
+            replay_done: false,
+            inputs: controls.into_iter().enumerate().map(|(player, controls)| PlayerInput::new(controls, player as u32)).collect(),
+            glyphs,
+            assets,
+            win,
         }
-
-        self.win.set_size(Size {width: (self.line_len * BLOCK_SIZE) as f64, height: (self.num_lines * BLOCK_SIZE) as f64});
-        self.win.set_max_fps(MAX_FPS);
-        self.win.set_ups(MAX_FPS);
-        self.win.set_ups(MAX_FPS);
-        self.previous.resize(self.line_len * self.num_lines, -1);
     }
 
-    fn split_row(input: &str) -> Option<Vec<&str>> {
-        let mut output = Vec::<&str>::new();
-        let mut flag = false;
-
-        let mut curr = input;
-        while curr != "" {
-            let splitted = Game::take_and_skip(curr, ',');
-            match splitted {
-                Some((x, y)) => {
-                    output.push(x);
-                    curr = y;
-                },
-                None => {
-                    flag = true;
-                    break;
-                },
-            }
-        }
-
-        match flag {
-            false => Some(output),
-            true => None,
+    /// The grid plus the HUD strip below it.
+    fn window_size(world: &World) -> Size {
+        Size {
+            width: (world.line_len * BLOCK_SIZE) as f64,
+            height: (world.num_lines * BLOCK_SIZE) as f64 + HUD_HEIGHT,
         }
     }
 
-    fn add_row(&mut self, input: Vec<&str>) -> Result<(), RuntimeError> {
-
-        if self.line_len == 0 {
-            self.line_len = input.len();
-        };
-        if self.line_len != input.len() {
-            return Err(RuntimeError::InvalidFile);
-        }
-
-        let current_x = self.immovable.len();
-        let mut new_immovable_line = Vec::<Immovable>::new();
-        let mut new_immovable_texture_line = Vec::<G2dTexture>::new();
-
-        for i in 0..input.len() {
-            match input[i] {
-                "space" => {
-                    new_immovable_line.push(Immovable{
-                        type_object: ImmovableType::Background,
-                        x: (current_x * BLOCK_SIZE) as f64,
-                        y: (i * BLOCK_SIZE) as f64,
-                    });
-                    new_immovable_texture_line.push(Texture::from_path(
-                        &mut self.win.create_texture_context(),
-                        BACKGROUND_PATH,
-                        Flip::None,
-                        &TextureSettings::new()
-                    ).unwrap());
-                } ,
-                "wall" => {
-                    new_immovable_line.push(Immovable{
-                        type_object: ImmovableType::Wall,
-                        x: (current_x * BLOCK_SIZE) as f64,
-                        y: (i * BLOCK_SIZE) as f64,
-                    });
-                    new_immovable_texture_line.push(Texture::from_path(
-                        &mut self.win.create_texture_context(),
-                        WALL_PATH,
-                        Flip::None,
-                        &TextureSettings::new()
-                    ).unwrap());
-                } ,
-                "gold" => {
-                    new_immovable_line.push(Immovable{
-                        type_object: ImmovableType::Bag,
-                        x: (current_x * BLOCK_SIZE) as f64,
-                        y: (i * BLOCK_SIZE) as f64,
-                    });
-                    new_immovable_texture_line.push(Texture::from_path(
-                        &mut self.win.create_texture_context(),
-                        BAG_PATH,
-                        Flip::None,
-                        &TextureSettings::new()
-                    ).unwrap());
-                } ,
-                "diamond" => {
-                    new_immovable_line.push(Immovable{
-                        type_object: ImmovableType::Emerald,
-                        x: (current_x * BLOCK_SIZE) as f64,
-                        y: (i * BLOCK_SIZE) as f64,
-                    });
-                    new_immovable_texture_line.push(Texture::from_path(
-                        &mut self.win.create_texture_context(),
-                        EMERALD_PATH,
-                        Flip::None,
-                        &TextureSettings::new()
-                    ).unwrap());
-                    self.max_score += SCORE_PER_EMERALD;
-                } ,
-                "player" => {
-                    new_immovable_line.push(Immovable{
-                        type_object: ImmovableType::Background,
-                        x: (current_x * BLOCK_SIZE) as f64,
-                        y: (i * BLOCK_SIZE) as f64,
-                    });
-                    new_immovable_texture_line.push(Texture::from_path(
-                        &mut self.win.create_texture_context(),
-                        BACKGROUND_PATH,
-                        Flip::None,
-                        &TextureSettings::new()
-                    ).unwrap());
-                    self.movable.push(Movable{
-                        type_object: MovableType::Player,
-                        x: (current_x * BLOCK_SIZE) as f64,
-                        y: (i * BLOCK_SIZE) as f64,
-                        dir: Direction::None,
-                        activation_time: Instant::now(),
-                        previous_dir: Direction::Right,
-                    });
-                    self.movable_texture.push(Texture::from_path(
-                        &mut self.win.create_texture_context(),
-                        PLAYER_PATH,
-                        Flip::None,
-                        &TextureSettings::new()
-                    ).unwrap());
-                }
-                "spawn" => {
-                    new_immovable_line.push(Immovable{
-                        type_object: ImmovableType::Background,
-                        x: (current_x * BLOCK_SIZE) as f64,
-                        y: (i * BLOCK_SIZE) as f64,
-                    });
-                    new_immovable_texture_line.push(Texture::from_path(
-                        &mut self.win.create_texture_context(),
-                        BACKGROUND_PATH,
-                        Flip::None,
-                        &TextureSettings::new()
-                    ).unwrap());
-                    self.spawn_x = (current_x * BLOCK_SIZE) as f64;
-                    self.spawn_y = (i * BLOCK_SIZE) as f64;
-                    self.current_number_monsters += 1;
-                    self.movable.push(Movable{
-                        type_object: MovableType::Monster,
-                        x: (current_x * BLOCK_SIZE) as f64,
-                        y: (i * BLOCK_SIZE) as f64,
-                        dir: Direction::None,
-                        activation_time: Instant::now(),
-                        previous_dir: Direction::None,
-                    });
-                    self.movable_texture.push(Texture::from_path(
-                        &mut self.win.create_texture_context(),
-                        MONSTER_PATH,
-                        Flip::None,
-                        &TextureSettings::new()
-                    ).unwrap());
-                },
-                _ => break,
-            }
-        }
-
-        self.immovable.push(new_immovable_line);
-        self.immovable_texture.push(new_immovable_texture_line);
-        self.last_spawned = Instant::now();
-
-        Ok(())
+    /// Adjusts the window to a newly loaded level.
+    fn world_changed(&mut self) {
+        let world = &self.session.world;
+        self.win.set_title(world.name.clone());
+        self.win.set_size(Game::window_size(world));
+        self.playback = None;
+        self.replay_done = true;
     }
 
-    fn skip_next(input: &str, target: char) -> Option<&str> {
-        let mut chars = input.chars();
-        match chars.next() {
-            Some(ch) if ch == target => Some(chars.as_str()),
-            _ => None,
-        }
+    /// Feeds the inputs of `replay` into the game instead of the keyboard, skipping the title screen.
+    /// The session must have been started with the replay's seed.
+    pub fn play(&mut self, replay: Replay) {
+        self.session.state = GameState::Playing;
+        self.playback = Some(replay);
     }
-    
-    fn take_until(input: &str, target: char) -> (&str, &str) {
-        match input.find(target) {
-            Some(pos) => (&input[..pos], &input[pos..]),
-            None => (input, ""),
-        }
+
+    /// Captures every command of the first run into `replay`.
+    pub fn record(&mut self, replay: Replay) {
+        self.recording = Some(replay);
     }
-    
-    fn take_and_skip(input: &str, target: char) -> Option<(&str, &str)> {
-        let (first, second) = Game::take_until(input, target);
-        let second = Game::skip_next(second, target);
-        match second {
-            Some(x) => Some((first, x)),
-            None => None
-        }
+
+    pub fn take_recording(&mut self) -> Option<Replay> {
+        self.recording.take()
     }
 
-    pub fn print(&mut self) -> Result<(), RuntimeError> {
+    pub fn print(&mut self) -> Result<(), MapError> {
         while let Some(e) = self.win.next() {
-            if let Some(Button::Keyboard(key)) = e.press_args() {
-                self.key_down(key);
+            let actions: Vec<Action> = self.inputs.iter_mut().flat_map(|input| input.event(&e)).collect();
+            for action in actions {
+                match action {
+                    Action::Pause => self.session.toggle_pause(),
+                    Action::Restart => {
+                        let loaded = self.session.restart()?;
+                        if loaded {
+                            self.world_changed();
+                        }
+                    },
+                    Action::Quit => self.win.set_should_close(true),
+                    Action::Confirm => {
+                        let before = self.session.state;
+                        let loaded = self.session.confirm()?;
+                        if loaded {
+                            self.world_changed();
+                        }
+                        // The button that starts play may also fire; that press belongs to the screen, not the level.
+                        if before != GameState::Playing && self.session.state == GameState::Playing {
+                            for input in self.inputs.iter_mut() {
+                                input.take_commands();
+                            }
+                        }
+                    },
+                    _ => {},
+                }
             }
 
-            let mut cp = self.immovable_texture.clone();
-            let mut ci = self.immovable.clone();
-            let mut cpm = self.movable_texture.clone();
-            let mut cm = self.movable.clone();
-
-            self.win.draw_2d(&e, |c, g, _| {
-                for i in 0..cp.len() {
-                    for j in 0..cp[i].len() {
-                        image(&cp[i][j], c.transform.trans(ci[i][j].y, ci[i][j].x), g);
+            if e.update_args().is_some() {
+                // Ticks that come due while not playing are dropped, so resuming does not burst.
+                for _ in 0..self.clock.ticks_due() {
+                    let players = self.session.world.players.len();
+                    // Every input is drained, even those of players the level has no cell for, so nothing piles up.
+                    let mut pending: Vec<Vec<Command>> = self.inputs.iter_mut().map(PlayerInput::take_commands).collect();
+                    pending.truncate(players);
+                    if self.session.state != GameState::Playing {
+                        continue;
                     }
+                    let tick = self.session.world.tick + 1;
+                    let commands = match self.playback.as_mut() {
+                        Some(replay) => replay.commands_at(tick),
+                        None => pending,
+                    };
+                    let replay_done = self.replay_done;
+                    if let Some(replay) = self.recording.as_mut().filter(|_| !replay_done) {
+                        replay.record(tick, &commands);
+                    }
+                    self.session.step(&commands);
                 }
-                for i in 0..cpm.len() {
-                    image(&cpm[i], c.transform.trans(cm[i].y, cm[i].x), g);
-                }
-            });
-            cm = move_object(cm);
-            match self.check_for_colision(cm.clone(), cpm.clone(), ci.clone()) {
-                Err(e) => {
-                    return Err(e);
-                },
-                Ok((x,y)) => {
-                    cm = x.clone();
-                    cpm = y .clone();
-                }
-            }
-            match self.update_game(&mut ci, &mut cp, &mut cm, &mut cpm) {
-                Err(e) => {
-                    return Err(e);
-                },
-                _ => {}
             }
-        }
-        Ok(())
-    }
 
-    fn key_down(&mut self, key: keyboard::Key) {
-
-        let player_position = self.player_pos();
-        let X = (self.movable[player_position].x / (BLOCK_SIZE as f64)) as usize;
-        let Y = (self.movable[player_position].y / (BLOCK_SIZE as f64)) as usize;
-        match key {
-            Key::A | Key::Left => {
-                if Y > 0 && self.immovable[X][Y - 1].type_object != ImmovableType::Bag {
-                    self.movable[player_position].dir = Direction::Left;
-                };
-            },
-            Key::W | Key::Up => {
-                if X > 0 && self.immovable[X - 1][Y].type_object != ImmovableType::Bag {
-                    self.movable[player_position].dir = Direction::Up;
-                };
-            },
-            Key::D | Key::Right => {
-                if Y < self.line_len - 1 && self.immovable[X][Y + 1].type_object != ImmovableType::Bag {
-                    self.movable[player_position].dir = Direction::Right;
-                };
-            },
-            Key::S | Key::Down => {
-                if X < self.num_lines - 1 && self.immovable[X + 1][Y].type_object != ImmovableType::Bag {
-                    self.movable[player_position].dir = Direction::Down;
-                };
-            },
-            Key::Space => {
-                if self.shots < 1 {
-                    return;
-                }
-                self.shots -= 1;
-                let player_position = self.player_pos();
-                match self.movable[player_position].previous_dir {
-                    Direction::Right => {
-                        self.movable.push(Movable{
-                            type_object: MovableType::Shot,
-                            x: self.movable[player_position].x,
-                            y: self.movable[player_position].y + (BLOCK_SIZE as f64),
-                            dir: Direction::Right,
-                            activation_time: Instant::now(),
-                            previous_dir: Direction::Right,
-                        });
-                        self.movable_texture.push(Texture::from_path(
-                            &mut self.win.create_texture_context(),
-                            SHOT_PATH,
-                            Flip::None,
-                            &TextureSettings::new()
-                        ).unwrap());
-                    },
-                    Direction::Left => {
-                        self.movable.push(Movable{
-                            type_object: MovableType::Shot,
-                            x: self.movable[player_position].x,
-                            y: self.movable[player_position].y - (BLOCK_SIZE as f64),
-                            dir: Direction::Left,
-                            activation_time: Instant::now(),
-                            previous_dir: Direction::Left,
-                        });
-                        self.movable_texture.push(Texture::from_path(
-                            &mut self.win.create_texture_context(),
-                            SHOT_PATH,
-                            Flip::None,
-                            &TextureSettings::new()
-                        ).unwrap());
-                    },
-                    Direction::Up => {
-                        self.movable.push(Movable{
-                            type_object: MovableType::Shot,
-                            x: self.movable[player_position].x - (BLOCK_SIZE as f64),
-                            y: self.movable[player_position].y,
-                            dir: Direction::Up,
-                            activation_time: Instant::now(),
-                            previous_dir: Direction::Up,
-                        });
-                        self.movable_texture.push(Texture::from_path(
-                            &mut self.win.create_texture_context(),
-                            SHOT_PATH,
-                            Flip::None,
-                            &TextureSettings::new()
-                        ).unwrap());
-                    },
-                    Direction::Down => {
-                        self.movable.push(Movable{
-                            type_object: MovableType::Shot,
-                            x: self.movable[player_position].x + (BLOCK_SIZE as f64),
-                            y: self.movable[player_position].y,
-                            dir: Direction::Down,
-                            activation_time: Instant::now(),
-                            previous_dir: Direction::Down,
-                        });
-                        self.movable_texture.push(Texture::from_path(
-                            &mut self.win.create_texture_context(),
-                            SHOT_PATH,
-                            Flip::None,
-                            &TextureSettings::new()
-                        ).unwrap());
-                    },
-                    _ => {}
-                }
-            }
-            _ => {
-                self.movable[player_position].dir = Direction::None;
+            if e.render_args().is_some() {
+                let world = &self.session.world;
+                let assets = &self.assets;
+                let blink = (world.tick / 3).is_multiple_of(2);
+                let lines = Game::overlay(&self.session, &self.inputs[0].controls);
+                let hud = Game::hud(world);
+                let hud_top = (world.num_lines * BLOCK_SIZE) as f64;
+                let glyphs = &mut self.glyphs;
+
+                self.win.draw_2d(&e, |c, g, device| {
+                    for (x, row) in world.immovable.iter().enumerate() {
+                        for (y, cell) in row.iter().enumerate() {
+                            assets.draw(Game::immovable_sprite(cell), world.tick, (y * BLOCK_SIZE) as f64, (x * BLOCK_SIZE) as f64, &c, g);
+                        }
+                    }
+                    if let Some((x, y)) = world.exit {
+                        let cell = [(y * BLOCK_SIZE) as f64, (x * BLOCK_SIZE) as f64, BLOCK_SIZE as f64, BLOCK_SIZE as f64];
+                        Rectangle::new_border([1.0, 0.85, 0.0, 1.0], 2.0).draw(cell, &c.draw_state, c.transform, g);
+                    }
+                    for object in &world.movable {
+                        if object.type_object == MovableType::Player {
+                            let player = &world.players[object.owner];
+                            if ((blink && world.is_invulnerable(object.owner)) || player.is_out()) && !player.is_dying() {
+                                continue;
+                            }
+                        }
+                        // Bags about to fall shake from side to side.
+                        let wobble = if object.type_object == MovableType::FallingBag && object.dir == Direction::None {
+                            if (world.tick / 2).is_multiple_of(2) { -1.0 } else { 1.0 }
+                        } else {
+                            0.0
+                        };
+                        let dying = object.type_object == MovableType::Player && world.players[object.owner].is_dying();
+                        assets.draw(Game::movable_sprite(object, dying), world.tick, object.y.pixels() + wobble, object.x.pixels(), &c, g);
+                    }
+                    let [width, height] = c.get_view_size();
+                    if world.is_bonus_active() {
+                        Rectangle::new_border([0.9, 0.1, 0.1, 1.0], 2.0).draw([0.0, 0.0, width, hud_top], &c.draw_state, c.transform, g);
+                    }
+                    rectangle([0.0, 0.0, 0.0, 1.0], [0.0, hud_top, width, HUD_HEIGHT], c.transform, g);
+                    for (i, line) in hud.iter().enumerate() {
+                        let top = hud_top + 5.0 + HUD_LINE_HEIGHT * (i as f64 + 0.75);
+                        text::Text::new_color([1.0, 1.0, 1.0, 1.0], HUD_FONT_SIZE)
+                            .draw(line, glyphs, &c.draw_state, c.transform.trans(10.0, top), g)
+                            .unwrap();
+                    }
+                    if !lines.is_empty() {
+                        rectangle([0.0, 0.0, 0.0, 0.7], [0.0, 0.0, width, height], c.transform, g);
+                        let top = (height - LINE_HEIGHT * lines.len() as f64) / 2.0 + LINE_HEIGHT * 0.75;
+                        for (i, line) in lines.iter().enumerate() {
+                            let left = (width - glyphs.width(FONT_SIZE, line).unwrap_or(0.0)) / 2.0;
+                            text::Text::new_color([1.0, 1.0, 1.0, 1.0], FONT_SIZE)
+                                .draw(line, glyphs, &c.draw_state, c.transform.trans(left, top + LINE_HEIGHT * i as f64), g)
+                                .unwrap();
+                        }
+                    }
+                    glyphs.factory.encoder.flush(device);
+                });
             }
         }
+        Ok(())
     }
 
-    fn player_pos(&mut self) -> usize {
-        for i in 0..self.movable.len() {
-            if self.movable[i].type_object == MovableType::Player {
-                return i;
-            };
+    /// The two lines of the status strip. With two players each gets their own score, lives and shots.
+    fn hud(world: &World) -> [String; 2] {
+        let label = |player: usize| if world.players.len() > 1 { format!("P{} ", player + 1) } else { String::new() };
+        let mut goals = String::new();
+        let mut status = world.name.clone();
+        for (i, player) in world.players.iter().enumerate() {
+            goals += &format!("{}Score {}   ", label(i), player.score);
+            status += &format!("   {}Lives {}   Shots {}", label(i), player.lives, player.shots);
         }
-        0
-    }
-
-    fn stop_player_movement(&mut self) {
-        let player_position = self.player_pos();
-        if self.movable[player_position].dir != Direction::None {
-            self.movable[player_position].previous_dir = self.movable[player_position].dir;
-        };
-        self.movable[player_position].dir = Direction::None;
-    }
-
-    fn update_game(&mut self, 
-        cp_immovable: &mut Vec<Vec<Immovable>>, 
-        cp_immovable_texture: &mut Vec<Vec<G2dTexture>>,
-        cp_movable: &mut Vec<Movable>,
-        cp_movable_texture: &mut Vec<G2dTexture>) -> Result<(), RuntimeError> {
-
-            let player_position = self.player_pos();
-            let X = (self.movable[player_position].x / (BLOCK_SIZE as f64)) as usize;
-            let Y = (self.movable[player_position].y / (BLOCK_SIZE as f64)) as usize;
-            if cp_immovable[X][Y].type_object == ImmovableType::Emerald {
-                self.current_score += SCORE_PER_EMERALD;
-            };
-            if self.current_score >= self.max_score {
-                return Err(RuntimeError::Victory);
-            };
-            cp_immovable[X][Y].type_object = ImmovableType::Background;
-            cp_immovable_texture[X][Y] = 
-                Texture::from_path(
-                &mut self.win.create_texture_context(),
-                BACKGROUND_PATH,
-                Flip::None,
-                &TextureSettings::new()
-            ).unwrap();
-
-            self.immovable_texture = cp_immovable_texture.clone();
-            self.immovable = cp_immovable.clone();
-            self.movable_texture = cp_movable_texture.clone();
-            self.movable = cp_movable.clone();
-            self.activate_bags();
-            self.drop_bags();
-            self.stop_player_movement();
-            self.find_shortest_paths();
-            self.spawn_monster();
-            self.redirect_monsters();
-            
-            Ok(())
-    }
-
-    fn find_shortest_paths(&mut self) {
-        let player_pososition = self.player_pos();
-        let player_X = (self.movable[player_pososition].x / (BLOCK_SIZE as f64)) as usize;
-        let player_Y = (self.movable[player_pososition].y / (BLOCK_SIZE as f64)) as usize;
-        let mut visited = Vec::<bool>::new();
-        for i in 0..self.previous.len() {
-            self.previous[i] = -1;
-            visited.push(false);
+        goals += &format!("Emeralds {}", world.emeralds_left());
+        for objective in world.objectives.iter().filter(|&&objective| objective != Objective::CollectEmeralds) {
+            goals += &format!("   {}", objective.status(world));
         }
-        let mut queue = VecDeque::<usize>::new();
-        queue.push_back(player_X * self.line_len + player_Y);
-
-        while !queue.is_empty() {
-            let current = queue.pop_front().unwrap();
-            visited[current] = true;
-            let current_X = current / self.line_len;
-            let current_Y = current % self.line_len;
-
-            if current_Y < self.line_len - 1
-            && self.immovable[current_X][current_Y + 1].type_object == ImmovableType::Background 
-            && !visited[current + 1] {
-                queue.push_back(current + 1);
-                self.previous[current + 1] = current as i32;
-            };
-
-            if current_Y > 0 
-            && self.immovable[current_X][current_Y - 1].type_object == ImmovableType::Background 
-            && !visited[current - 1] {
-                queue.push_back(current - 1);
-                self.previous[current - 1] = current as i32;
-            };
-
-            if current_X < self.num_lines - 1 
-            && self.immovable[current_X + 1][current_Y].type_object == ImmovableType::Background 
-            && !visited[current + self.line_len]{
-                queue.push_back(current + self.line_len);
-                self.previous[current + self.line_len] = current as i32;
-            };
-
-            if current_X > 0 
-            && self.immovable[current_X - 1][current_Y].type_object == ImmovableType::Background 
-            && !visited[current - self.line_len]{
-                queue.push_back(current - self.line_len);
-                self.previous[current - self.line_len] = current as i32;
-            };
+        if world.is_bonus_active() {
+            status += &format!("   BONUS {}s", world.bonus_left().div_ceil(TICKS_PER_SECOND));
         }
-
+        [status, goals]
     }
 
-    fn redirect_monsters(&mut self) {
-        for i in 0..self.movable.len() {
-            if self.movable[i].type_object == MovableType::Monster {
-                let monster_X = (self.movable[i].x / (BLOCK_SIZE as f64)) as i32;
-                let monster_Y = (self.movable[i].y / (BLOCK_SIZE as f64)) as i32;
-
-                let previous_position = self.previous[(monster_X as usize) * self.line_len + (monster_Y as usize)] as i32;
-
-                let previous_X = previous_position / (self.line_len as i32);
-                let previous_Y = previous_position % (self.line_len as i32);
-                
-                if previous_X == monster_X - 1  {
-                    self.movable[i].dir = Direction::Up;
-                    self.movable[i].y = (monster_Y * (BLOCK_SIZE as i32)) as f64;
-                    continue;
-                }
-
-                if previous_X == monster_X + 1  {
-                    self.movable[i].dir = Direction::Down;
-                    self.movable[i].y = (monster_Y * (BLOCK_SIZE as i32)) as f64;
-                    continue;
-                }
-
-                if previous_Y == monster_Y - 1 {
-                    self.movable[i].dir = Direction::Left;
-                    self.movable[i].x = (monster_X * (BLOCK_SIZE as i32)) as f64;
-                    continue;
-                }
-
-                if previous_Y == monster_Y + 1 {
-                    self.movable[i].dir = Direction::Right;
-                    self.movable[i].x = (monster_X * (BLOCK_SIZE as i32)) as f64;
-                    continue;
-                }
-            }
+    /// Text drawn over the level for every state but `Playing`.
+    fn overlay(session: &Session, controls: &Controls) -> Vec<String> {
+        let confirm = controls.key_name(Action::Confirm);
+        let score = session.world.players.iter().map(|player| player.score.to_string()).collect::<Vec<String>>().join(" / ");
+        match session.state {
+            GameState::Playing => Vec::new(),
+            GameState::Title => vec![session.world.name.clone(), format!("Press {} to start", confirm)],
+            GameState::Paused => vec!["PAUSED".to_string(), format!("Press {} to resume", controls.key_name(Action::Pause))],
+            GameState::LevelComplete => vec![
+                format!("Level {} complete!", session.campaign.level_number()),
+                format!("Score: {}", score),
+                format!("Press {} to continue", confirm),
+            ],
+            GameState::GameOver => vec![
+                "GAME OVER".to_string(),
+                format!("Final score: {}", score),
+                format!("Press {} to restart", confirm),
+            ],
+            GameState::Victory => vec![
+                "Campaign complete!".to_string(),
+                format!("Final score: {}", score),
+                format!("Press {} to play again", confirm),
+            ],
         }
     }
 
-    fn activate_bags(&mut self) {
-        let player_position = self.player_pos();
-        let player_X = (self.movable[player_position].x / (BLOCK_SIZE as f64)) as usize;
-        let player_Y = (self.movable[player_position].y / (BLOCK_SIZE as f64)) as usize;
-
-        if player_X > 0 && self.immovable[player_X - 1][player_Y].type_object == ImmovableType::Bag {
-            self.immovable[player_X - 1][player_Y].type_object = ImmovableType::Background;
-            self.immovable_texture[player_X - 1][player_Y] = Texture::from_path(
-                &mut self.win.create_texture_context(),
-                BACKGROUND_PATH,
-                Flip::None,
-                &TextureSettings::new()
-            ).unwrap();
-            self.movable.push(Movable{
-                type_object: MovableType::FallingBag,
-                x: ((player_X - 1) * BLOCK_SIZE) as f64,
-                y: (player_Y * BLOCK_SIZE) as f64,
-                dir: Direction::None,
-                activation_time: Instant::now(),
-                previous_dir: Direction::None,
-            });
-            self.movable_texture.push(Texture::from_path(
-                &mut self.win.create_texture_context(),
-                BAG_PATH,
-                Flip::None,
-                &TextureSettings::new()
-            ).unwrap());
-        };
-    }
-
-    fn drop_bags(&mut self) {
-        for i in 0..self.movable.len() {
-            if self.movable[i].type_object == MovableType::FallingBag 
-            && self.movable[i].dir == Direction::None 
-            && self.movable[i].activation_time.elapsed() >= Duration::from_secs(TIME_TO_FALL) {
-                self.movable[i].dir = Direction::Down;
-                self.movable_texture[i] = Texture::from_path(
-                    &mut self.win.create_texture_context(),
-                    FALLING_BAG_PATH,
-                    Flip::None,
-                    &TextureSettings::new()
-                ).unwrap();
-            }
+    fn immovable_sprite(object: &Immovable) -> Sprite {
+        match object.type_object {
+            ImmovableType::Background => Sprite::Background,
+            ImmovableType::Wall => Sprite::Wall,
+            ImmovableType::Emerald => Sprite::Emerald,
+            ImmovableType::Bag => Sprite::Bag,
+            ImmovableType::Bonus => Sprite::Bonus,
         }
     }
 
-    fn check_for_colision(&mut self, 
-        input: Vec<Movable>, 
-        input_texture: Vec<G2dTexture>,
-        input_immovable: Vec<Vec<Immovable>>) 
-        -> Result<(Vec<Movable>, Vec<G2dTexture>), RuntimeError> {
-        
-            let mut output = Vec::<Movable>::new();
-            let mut output_texture = Vec::<G2dTexture>::new();
-            let mut to_remove = Vec::<usize>::new();
-    
-            for i in 0..input.len(){
-                match input[i].type_object {
-                    MovableType::Player => {
-                        for j in 0..input.len() {
-                            match input[j].type_object {
-                                MovableType::Monster | MovableType::FallingBag => {
-                                    if overlaps(input[i], input[j]) {
-                                        return Err(RuntimeError::PlayerDead);
-                                    }
-                                }
-                                MovableType::Crashed => {
-                                    if overlaps(input[i], input[j]) {
-                                        self.shots += 1;
-                                        to_remove.push(j);
-                                    };
-                                },
-                                _ => {},
-                            }
-                        }
-                        output.push(input[i]);
-                        output_texture.push(input_texture[i].clone());
-                    }
-                    MovableType::Monster => {
-                        let mut monster_dead = false;
-                        for j in 0..input.len() {
-                            if input[j].type_object == MovableType::Shot 
-                            || input[j].type_object == MovableType::FallingBag {
-                                if overlaps(input[i], input[j]) {
-                                    self.current_number_monsters -= 1;
-                                    monster_dead = true;
-                                    to_remove.push(j);
-                                    break;
-                                }
-                            }
-                        }
-                        if !monster_dead {
-                            output.push(input[i]);
-                            output_texture.push(input_texture[i].clone());
-                        };
-                    }
-                    MovableType::FallingBag => {
-                        if to_remove.iter().any(|&k| k == i) {
-                            continue;
-                        }
-                        if bag_or_shot_crashed(input[i], input_immovable.clone()) {
-                            output.push(Movable{
-                                type_object: MovableType::Crashed,
-                                x: input[i].x,
-                                y: input[i].y,
-                                dir: Direction::None,
-                                activation_time: Instant::now(),
-                                previous_dir: Direction::None,
-                            });
-                            output_texture.push(Texture::from_path(
-                                &mut self.win.create_texture_context(),
-                                BROKEN_BAG_PATH,
-                                Flip::None,
-                                &TextureSettings::new()
-                            ).unwrap());
-                            continue;
-                        }
-                        output.push(input[i]);
-                        output_texture.push(input_texture[i].clone());
-                    }
-                    MovableType::Shot => {
-                        if !to_remove.iter().any(|&k| k == i) && !bag_or_shot_crashed(input[i], input_immovable.clone()) {
-                            output.push(input[i]);
-                            output_texture.push(input_texture[i].clone());
-                        }
-                    },
-                    MovableType::Crashed => {
-                        if !to_remove.iter().any(|&k| k == i) {
-                            output.push(input[i]);
-                            output_texture.push(input_texture[i].clone());
-                        }
-                    }
-                    _ => {}
+    fn movable_sprite(object: &Movable, dying: bool) -> Sprite {
+        match object.type_object {
+            MovableType::Player if dying => Sprite::Death,
+            MovableType::Player => {
+                let facing = if object.dir == Direction::None { object.previous_dir } else { object.dir };
+                match facing {
+                    Direction::Left => Sprite::PlayerLeft,
+                    Direction::Up => Sprite::PlayerUp,
+                    Direction::Down => Sprite::PlayerDown,
+                    Direction::Right | Direction::None => Sprite::PlayerRight,
                 }
-            }
-    
-            Ok((output, output_texture))
-    }
-
-    fn spawn_monster(&mut self) {
-        if self.current_number_monsters < MAX_MONSTERS
-        && self.last_spawned.elapsed() >= Duration::from_secs(TIME_TO_SPAWN) {
-            self.movable.push(Movable{
-                type_object: MovableType::Monster,
-                x: self.spawn_x,
-                y: self.spawn_y,
-                dir: Direction::None,
-                activation_time: Instant::now(),
-                previous_dir: Direction::None,
-            });
-            self.movable_texture.push(Texture::from_path(
-                &mut self.win.create_texture_context(),
-                MONSTER_PATH,
-                Flip::None,
-                &TextureSettings::new()
-            ).unwrap());
-            self.last_spawned = Instant::now();
-            self.current_number_monsters += 1;
+            },
+            MovableType::Monster => Sprite::Monster,
+            MovableType::Hobbin => Sprite::Hobbin,
+            MovableType::FallingBag => {
+                if object.dir == Direction::Down {
+                    Sprite::FallingBag
+                } else {
+                    Sprite::Bag
+                }
+            },
+            MovableType::Shot => Sprite::Shot,
+            MovableType::Crashed | MovableType::Converted => Sprite::BrokenBag,
         }
     }
-}
\ No newline at end of file
+}
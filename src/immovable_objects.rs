@@ -1,8 +1,6 @@
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Immovable {
     pub type_object: ImmovableType,
-    pub x: f64,
-    pub y: f64,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -11,4 +9,6 @@ pub enum ImmovableType {
     Wall,
     Emerald,
     Bag,
+    /// Eating it makes monsters edible for a while.
+    Bonus,
 }
\ No newline at end of file
@@ -0,0 +1,471 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use crate::clock::*;
+use crate::movable_objects::*;
+use crate::objective::*;
+use crate::scoring::*;
+use crate::world::*;
+
+pub const LEVEL_MAGIC: &str = "digger-level";
+pub const LEVEL_VERSION: u32 = 1;
+const DEFAULT_LIVES: usize = 3;
+/// Player cells a map may have, one per player in co-op.
+pub const MAX_PLAYERS: usize = 2;
+const DEFAULT_BONUS_AFTER: usize = 5;
+const DEFAULT_BONUS_TIME: u64 = 10;
+const DEFAULT_PLAYER_SPEED: u64 = 6;
+
+/// What a single map cell starts out as.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Cell {
+    Space,
+    Wall,
+    Gold,
+    Diamond,
+    Player,
+    Spawn,
+    /// Where the player has to go on levels with the `exit` objective.
+    Exit,
+}
+
+/// Where in a map file a problem was found. Lines and columns start at 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+    pub width: usize,
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    Io { path: String, error: io::Error },
+    EmptyFile { path: String },
+    Syntax { at: Location, message: String },
+    UnknownToken { at: Location, token: String },
+    RaggedRow { at: Location, expected: usize, found: usize },
+    MissingPlayer { path: String },
+    MultiplePlayers { at: Location },
+    MissingSpawn { path: String },
+}
+
+impl MapError {
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            MapError::Syntax { at, .. }
+            | MapError::UnknownToken { at, .. }
+            | MapError::RaggedRow { at, .. }
+            | MapError::MultiplePlayers { at } => Some(at),
+            _ => None,
+        }
+    }
+
+    fn set_path(&mut self, new_path: &str) {
+        match self {
+            MapError::Io { path, .. }
+            | MapError::EmptyFile { path }
+            | MapError::MissingPlayer { path }
+            | MapError::MissingSpawn { path } => *path = new_path.to_string(),
+            MapError::Syntax { at, .. }
+            | MapError::UnknownToken { at, .. }
+            | MapError::RaggedRow { at, .. }
+            | MapError::MultiplePlayers { at } => at.path = new_path.to_string(),
+        }
+    }
+
+    /// The error message followed by the offending line of `source` with a caret under the problem.
+    pub fn report(&self, source: &str) -> String {
+        let mut output = format!("error: {}", self);
+        if let Some(at) = self.location() {
+            let text = source.lines().nth(at.line - 1).unwrap_or("");
+            let margin = " ".repeat(at.line.to_string().len());
+            output.push_str(&format!("\n{}--> {}:{}:{}", margin, at.path, at.line, at.column));
+            output.push_str(&format!("\n{} |", margin));
+            output.push_str(&format!("\n{} | {}", at.line, text));
+            output.push_str(&format!("\n{} | {}{}", margin, " ".repeat(at.column - 1), "^".repeat(at.width.max(1))));
+        }
+        output
+    }
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapError::Io { path, error } => write!(f, "cannot read map {}: {}", path, error),
+            MapError::EmptyFile { path } => write!(f, "map {} has no grid", path),
+            MapError::Syntax { message, .. } => write!(f, "{}", message),
+            MapError::UnknownToken { token, .. } => write!(f, "unknown token `{}`", token),
+            MapError::RaggedRow { expected, found, .. } => write!(f, "row has {} cells, expected {}", found, expected),
+            MapError::MissingPlayer { path } => write!(f, "map {} has no player cell", path),
+            MapError::MultiplePlayers { .. } => write!(f, "map has more than {} player cells", MAX_PLAYERS),
+            MapError::MissingSpawn { path } => write!(f, "map {} has no spawn cell", path),
+        }
+    }
+}
+
+/// A parsed level file: metadata plus the grid of starting cells.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Level {
+    pub name: String,
+    pub author: String,
+    /// Seconds the player has to finish the level, 0 for no limit.
+    pub time_limit: u64,
+    pub spawn: SpawnSchedule,
+    /// Lives each player starts with, unless carried over from a previous level.
+    pub lives: usize,
+    pub gold: GoldRules,
+    pub scoring: ScoringRules,
+    /// Everything that has to be done to win, all emeralds unless the map says otherwise.
+    pub objectives: Vec<Objective>,
+    /// Kinds of monster the spawn point produces, repeated in this order.
+    pub monsters: Vec<MovableType>,
+    /// Seconds after which a Nobbin turns into a Hobbin, 0 for never.
+    pub hobbin_after: u64,
+    /// Kills after which the bonus appears, 0 for never.
+    pub bonus_after: usize,
+    /// Seconds bonus mode lasts.
+    pub bonus_time: u64,
+    /// Cells per second the player covers while a direction is held.
+    pub player_speed: u64,
+    pub cells: Vec<Vec<Cell>>,
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Level::new()
+    }
+}
+
+fn parse_number(value: &str) -> Result<usize, String> {
+    value.parse::<usize>().map_err(|_| format!("`{}` is not a number", value))
+}
+
+fn parse_monster(value: &str) -> Result<MovableType, String> {
+    match value {
+        "nobbin" => Ok(MovableType::Monster),
+        "hobbin" => Ok(MovableType::Hobbin),
+        _ => Err(format!("`{}` is not a monster (nobbin or hobbin)", value)),
+    }
+}
+
+fn parse_flag(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" => Ok(true),
+        "false" | "no" => Ok(false),
+        _ => Err(format!("`{}` is not true or false", value)),
+    }
+}
+
+fn parse_cell(token: &str) -> Option<Cell> {
+    match token {
+        "space" => Some(Cell::Space),
+        "wall" => Some(Cell::Wall),
+        "gold" => Some(Cell::Gold),
+        "diamond" => Some(Cell::Diamond),
+        "player" => Some(Cell::Player),
+        "spawn" => Some(Cell::Spawn),
+        "exit" => Some(Cell::Exit),
+        _ => None,
+    }
+}
+
+fn default_legend() -> Vec<(char, Cell)> {
+    vec![
+        ('.', Cell::Space),
+        ('#', Cell::Wall),
+        ('$', Cell::Gold),
+        ('*', Cell::Diamond),
+        ('P', Cell::Player),
+        ('S', Cell::Spawn),
+        ('E', Cell::Exit),
+    ]
+}
+
+fn at(line: usize, column: usize, width: usize) -> Location {
+    Location {
+        path: String::new(),
+        line,
+        column,
+        width,
+    }
+}
+
+fn syntax(line: usize, text: &str, message: &str) -> MapError {
+    let indent = text.len() - text.trim_start().len();
+    MapError::Syntax {
+        at: at(line, indent + 1, text.trim().chars().count()),
+        message: message.to_string(),
+    }
+}
+
+#[derive(PartialEq)]
+enum Section {
+    None,
+    Meta,
+    Legend,
+    Grid,
+}
+
+impl Level {
+    pub fn new() -> Self {
+        Level {
+            name: String::from("DIGGER"),
+            author: String::new(),
+            time_limit: 0,
+            spawn: SpawnSchedule::default(),
+            lives: DEFAULT_LIVES,
+            gold: GoldRules::default(),
+            scoring: ScoringRules::default(),
+            objectives: vec![Objective::CollectEmeralds],
+            monsters: vec![MovableType::Monster],
+            hobbin_after: 0,
+            bonus_after: DEFAULT_BONUS_AFTER,
+            bonus_time: DEFAULT_BONUS_TIME,
+            player_speed: DEFAULT_PLAYER_SPEED,
+            cells: Vec::<Vec<Cell>>::new(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, MapError> {
+        let source = fs::read_to_string(path).map_err(|error| MapError::Io {
+            path: path.to_string(),
+            error,
+        })?;
+        Level::parse(&source).map_err(|mut e| {
+            e.set_path(path);
+            e
+        })
+    }
+
+    /// Parses either the sectioned format or the legacy comma-separated one.
+    pub fn parse(input: &str) -> Result<Self, MapError> {
+        if input.trim().is_empty() {
+            return Err(MapError::EmptyFile { path: String::new() });
+        }
+        let level = match input.lines().find(|line| !line.trim().is_empty()) {
+            Some(first) if first.trim().starts_with(LEVEL_MAGIC) => Level::parse_sectioned(input)?,
+            _ => Level::parse_legacy(input)?,
+        };
+        level.check()?;
+        Ok(level)
+    }
+
+    fn check(&self) -> Result<(), MapError> {
+        if self.cells.is_empty() {
+            return Err(MapError::EmptyFile { path: String::new() });
+        }
+        if !self.cells.iter().flatten().any(|&cell| cell == Cell::Player) {
+            return Err(MapError::MissingPlayer { path: String::new() });
+        }
+        if !self.cells.iter().flatten().any(|&cell| cell == Cell::Spawn) {
+            return Err(MapError::MissingSpawn { path: String::new() });
+        }
+        Ok(())
+    }
+
+    fn parse_legacy(input: &str) -> Result<Self, MapError> {
+        let mut level = Level::new();
+        let mut players = 0;
+        for (number, line) in input.lines().enumerate() {
+            let number = number + 1;
+            let splitted = Level::split_row(line).ok_or_else(|| MapError::Syntax {
+                at: at(number, line.len() + 1, 1),
+                message: String::from("expected `,` after the last token"),
+            })?;
+            let mut row = Vec::<Cell>::new();
+            for (offset, token) in splitted {
+                let location = at(number, line[..offset].chars().count() + 1, token.chars().count());
+                let cell = parse_cell(token).ok_or_else(|| MapError::UnknownToken {
+                    at: location.clone(),
+                    token: token.to_string(),
+                })?;
+                Level::check_player(cell, &location, &mut players)?;
+                row.push(cell);
+            }
+            level.push_row(row, number, line.chars().count())?;
+        }
+        Ok(level)
+    }
+
+    fn parse_sectioned(input: &str) -> Result<Self, MapError> {
+        let mut level = Level::new();
+        let mut legend = default_legend();
+        let mut section = Section::None;
+        let mut players = 0;
+        let mut lines = input.lines().enumerate().skip_while(|(_, line)| line.trim().is_empty());
+
+        let (number, header) = lines.next().unwrap_or((0, ""));
+        let version = header.trim()[LEVEL_MAGIC.len()..].trim();
+        if version.parse::<u32>() != Ok(LEVEL_VERSION) {
+            return Err(syntax(number + 1, header, &format!("unsupported level version, expected `{} {}`", LEVEL_MAGIC, LEVEL_VERSION)));
+        }
+
+        for (number, line) in lines {
+            let number = number + 1;
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with(';') {
+                continue;
+            }
+            match trimmed {
+                "[meta]" => {
+                    section = Section::Meta;
+                    continue;
+                },
+                "[legend]" => {
+                    section = Section::Legend;
+                    continue;
+                },
+                "[grid]" => {
+                    section = Section::Grid;
+                    continue;
+                },
+                _ => {},
+            }
+            match section {
+                Section::None => return Err(syntax(number, line, "expected a section header such as `[grid]`")),
+                Section::Meta => {
+                    let (key, value) = Level::split_pair(trimmed).ok_or_else(|| syntax(number, line, "expected `key = value`"))?;
+                    level.set_meta(key, value).map_err(|message| syntax(number, line, &message))?;
+                },
+                Section::Legend => {
+                    let (key, value) = Level::split_pair(trimmed).ok_or_else(|| syntax(number, line, "expected `symbol = token`"))?;
+                    let mut chars = key.chars();
+                    let symbol = match (chars.next(), chars.next()) {
+                        (Some(ch), None) => ch,
+                        _ => return Err(syntax(number, line, "legend symbols must be a single character")),
+                    };
+                    let cell = parse_cell(value).ok_or_else(|| MapError::UnknownToken {
+                        at: at(number, line.find(value).unwrap_or(0) + 1, value.chars().count()),
+                        token: value.to_string(),
+                    })?;
+                    legend.retain(|&(ch, _)| ch != symbol);
+                    legend.push((symbol, cell));
+                },
+                Section::Grid => {
+                    let indent = line.len() - line.trim_start().len();
+                    let mut row = Vec::<Cell>::new();
+                    for (column, ch) in trimmed.chars().enumerate() {
+                        let location = at(number, indent + column + 1, 1);
+                        let cell = match legend.iter().find(|&&(symbol, _)| symbol == ch) {
+                            Some(&(_, cell)) => cell,
+                            None => return Err(MapError::UnknownToken {
+                                at: location,
+                                token: ch.to_string(),
+                            }),
+                        };
+                        Level::check_player(cell, &location, &mut players)?;
+                        row.push(cell);
+                    }
+                    level.push_row(row, number, indent + trimmed.chars().count())?;
+                },
+            }
+        }
+        Ok(level)
+    }
+
+    fn check_player(cell: Cell, location: &Location, players: &mut usize) -> Result<(), MapError> {
+        if cell != Cell::Player {
+            return Ok(());
+        }
+        if *players == MAX_PLAYERS {
+            return Err(MapError::MultiplePlayers { at: location.clone() });
+        }
+        *players += 1;
+        Ok(())
+    }
+
+    fn set_meta(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "name" => self.name = value.to_string(),
+            "author" => self.author = value.to_string(),
+            "time_limit" => self.time_limit = value.parse::<u64>().map_err(|_| format!("`{}` is not a number of seconds", value))?,
+            "max_monsters" => self.spawn.max_concurrent = parse_number(value)?,
+            "total_monsters" => self.spawn.total = parse_number(value)?,
+            "spawn_interval" => self.spawn.interval = value.parse::<u64>().map_err(|_| format!("`{}` is not a number of seconds", value))? * TICKS_PER_SECOND,
+            "spawn_order" => self.spawn.order = match value {
+                "round_robin" => SpawnOrder::RoundRobin,
+                "random" => SpawnOrder::Random,
+                _ => return Err(format!("`{}` is not a spawn order (round_robin or random)", value)),
+            },
+            "lives" => match value.parse::<usize>() {
+                Ok(lives) if lives > 0 => self.lives = lives,
+                _ => return Err(format!("`{}` is not a positive number of lives", value)),
+            },
+            "gold_recharges" => self.gold.recharges_weapon = parse_flag(value)?,
+            "monsters_eat_gold" => self.gold.monsters_eat = parse_flag(value)?,
+            "score_emerald" => self.scoring.emerald = parse_number(value)?,
+            "score_gold" => self.scoring.gold = parse_number(value)?,
+            "score_monster_shot" => self.scoring.monster_shot = parse_number(value)?,
+            "score_monster_crushed" => self.scoring.monster_crushed = parse_number(value)?,
+            "streak_bonus" => self.scoring.streak_bonus = parse_number(value)?,
+            "streak_length" => self.scoring.streak_length = parse_number(value)?,
+            "extra_life_every" => self.scoring.extra_life_every = parse_number(value)?,
+            "objective" => self.objectives = Objective::parse_list(value)?,
+            "monsters" => self.monsters = value.split(',').map(|kind| parse_monster(kind.trim())).collect::<Result<Vec<MovableType>, String>>()?,
+            "bonus_after" => self.bonus_after = parse_number(value)?,
+            "bonus_time" => self.bonus_time = value.parse::<u64>().map_err(|_| format!("`{}` is not a number of seconds", value))?,
+            "score_monster_eaten" => self.scoring.monster_eaten = parse_number(value)?,
+            "hobbin_after" => self.hobbin_after = value.parse::<u64>().map_err(|_| format!("`{}` is not a number of seconds", value))?,
+            "player_speed" => match value.parse::<u64>() {
+                Ok(speed) if speed > 0 && speed <= TICKS_PER_SECOND => self.player_speed = speed,
+                _ => return Err(format!("`{}` is not a speed between 1 and {} cells per second", value, TICKS_PER_SECOND)),
+            },
+            _ => return Err(format!("unknown metadata key `{}`", key)),
+        }
+        Ok(())
+    }
+
+    fn push_row(&mut self, row: Vec<Cell>, line: usize, width: usize) -> Result<(), MapError> {
+        let expected = self.cells.first().map_or(row.len(), |first| first.len());
+        if row.is_empty() || row.len() != expected {
+            return Err(MapError::RaggedRow {
+                at: at(line, 1, width),
+                expected,
+                found: row.len(),
+            });
+        }
+        self.cells.push(row);
+        Ok(())
+    }
+
+    fn split_pair(input: &str) -> Option<(&str, &str)> {
+        let (key, value) = Level::take_and_skip(input, '=')?;
+        Some((key.trim(), value.trim()))
+    }
+
+    /// Splits a legacy row into its tokens, each paired with its byte offset in `input`.
+    fn split_row(input: &str) -> Option<Vec<(usize, &str)>> {
+        let mut output = Vec::<(usize, &str)>::new();
+
+        let mut curr = input;
+        while !curr.is_empty() {
+            let (x, y) = Level::take_and_skip(curr, ',')?;
+            output.push((input.len() - curr.len(), x));
+            curr = y;
+        }
+
+        Some(output)
+    }
+
+    fn skip_next(input: &str, target: char) -> Option<&str> {
+        let mut chars = input.chars();
+        match chars.next() {
+            Some(ch) if ch == target => Some(chars.as_str()),
+            _ => None,
+        }
+    }
+
+    fn take_until(input: &str, target: char) -> (&str, &str) {
+        match input.find(target) {
+            Some(pos) => (&input[..pos], &input[pos..]),
+            None => (input, ""),
+        }
+    }
+
+    fn take_and_skip(input: &str, target: char) -> Option<(&str, &str)> {
+        let (first, second) = Level::take_until(input, target);
+        let second = Level::skip_next(second, target)?;
+        Some((first, second))
+    }
+}
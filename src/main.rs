@@ -1,201 +1,1162 @@
+mod assets;
+mod campaign;
+mod clock;
+mod controls;
 mod game;
 mod immovable_objects;
+mod level;
 mod movable_objects;
+mod objective;
+mod replay;
+mod scoring;
+mod session;
+mod validate;
+mod world;
 
-use immovable_objects::*;
-use movable_objects::*;
+use assets::*;
+use campaign::*;
+use clock::*;
+use controls::*;
 use game::*;
-use piston_window::*;
+use level::*;
+use replay::*;
+use session::*;
+use validate::*;
+use std::env;
+use std::fs;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 const MAP_PATH: &str = "map.txt";
+const ATLAS_PATH: &str = "atlas.txt";
+const CONTROLS_PATH: &str = "controls.txt";
+
+fn flag_value(args: &[String], flag: &str) -> Option<String> {
+    let position = args.iter().position(|arg| arg == flag)?;
+    args.get(position + 1).cloned()
+}
+
+/// Runs `digger validate <map>` and returns the process exit code.
+fn validate_map(path: &str) -> i32 {
+    let level = match Level::load(path) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e.report(&fs::read_to_string(path).unwrap_or_default()));
+            return 1;
+        }
+    };
+    let problems = validate(&level);
+    for problem in &problems {
+        println!("{}: {}", path, problem);
+    }
+    if problems.iter().any(|problem| problem.severity == Severity::Error) {
+        return 1;
+    }
+    println!("{}: ok", path);
+    0
+}
 
 fn main() {
 
-    let mut g = Game::new();
-    g.initialize(MAP_PATH);
-    g.print();
-    return;
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("validate") {
+        match args.get(2) {
+            Some(path) => process::exit(validate_map(path)),
+            None => {
+                println!("usage: digger validate <map>");
+                process::exit(2);
+            }
+        }
+    }
+    let replay_path = flag_value(&args, "--replay");
+    let record_path = flag_value(&args, "--record");
+
+    let campaign = match flag_value(&args, "--campaign") {
+        Some(path) => match Campaign::load(&path) {
+            Ok(x) => x,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        },
+        None => Campaign::single(MAP_PATH),
+    };
+    if campaign.levels.len() > 1 && (replay_path.is_some() || record_path.is_some()) {
+        println!("--record and --replay only work on a single map");
+        return;
+    }
+
+    let map_contents = fs::read_to_string(campaign.current_path()).unwrap_or_default();
+    let replay = match replay_path.map(|path| Replay::load(&path, &map_contents)).transpose() {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let seed = match (&replay, SystemTime::now().duration_since(UNIX_EPOCH)) {
+        (Some(replay), _) => replay.seed,
+        (None, Ok(x)) => x.as_nanos() as u64,
+        (None, Err(_)) => 0,
+    };
+    let session = match Session::new(campaign, seed) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e.report(&map_contents));
+            return;
+        }
+    };
+
+    let atlas = match Atlas::load(ATLAS_PATH) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let mut controls = Vec::<Controls>::new();
+    for player in 0..MAX_PLAYERS {
+        match Controls::load(CONTROLS_PATH, player) {
+            Ok(x) => controls.push(x),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        }
+    }
+
+    let mut g = Game::new(session, atlas, controls, Box::new(RealClock::new()));
+    if let Some(replay) = replay {
+        g.play(replay);
+    }
+    if record_path.is_some() {
+        g.record(Replay::new(map_hash(&map_contents), seed));
+    }
+
+    if let Err(e) = g.print() {
+        let path = g.session.campaign.current_path();
+        println!("{}", e.report(&fs::read_to_string(path).unwrap_or_default()));
+    }
+
+    if let (Some(path), Some(replay)) = (record_path, g.take_recording()) {
+        if let Err(e) = replay.save(&path) {
+            println!("{}", e);
+        }
+    }
 
 }
 
 #[cfg(test)]
 mod tests{
+    use crate::assets::*;
+    use crate::campaign::*;
+    use crate::clock::*;
+    use crate::controls::*;
+    use crate::immovable_objects::*;
+    use crate::level::*;
+    use crate::movable_objects::*;
+    use crate::objective::*;
+    use crate::replay::*;
+    use crate::scoring::*;
+    use crate::session::*;
+    use crate::validate::*;
+    use crate::world::*;
+    use piston_window::{Button, ButtonArgs, ButtonState, ControllerAxisArgs, ControllerButton, ControllerHat, Event, HatState, Input, Key, Motion};
+
     #[test]
     fn test_overlapping() {
 
-        assert_eq!(
-            super::overlaps(super::Movable{
-                type_object: super::MovableType::Player,
-                x: 0.0,
-                y: 0.0,
-                dir: super::Direction::None,
-                activation_time: std::time::Instant::now(),
-                previous_dir: super::Direction::None,
+        assert!(!overlaps(Movable{
+                type_object: MovableType::Player,
+                x: Coord::at(0),
+                y: Coord::at(0),
+                dir: Direction::None,
+                activation_time: 0,
+                previous_dir: Direction::None,
+                owner: 0,
             }, 
-            super::Movable{
-                type_object: super::MovableType::Player,
-                x: 0.0,
-                y: 1.0 + (super::BLOCK_SIZE as f64),
-                dir: super::Direction::None,
-                activation_time: std::time::Instant::now(),
-                previous_dir: super::Direction::None,
-            }), 
-            false);
-
-        assert_eq!(
-            super::overlaps(super::Movable{
-                type_object: super::MovableType::Player,
-                x: 2.0 * (super::BLOCK_SIZE as f64) + 15.0,
-                y: 3.0 * (super::BLOCK_SIZE as f64),
-                dir: super::Direction::None,
-                activation_time: std::time::Instant::now(),
-                previous_dir: super::Direction::None,
+            Movable{
+                type_object: MovableType::Player,
+                x: Coord::at(0),
+                y: Coord { cell: 1, step: 20 },
+                dir: Direction::None,
+                activation_time: 0,
+                previous_dir: Direction::None,
+                owner: 0,
+            }));
+
+        assert!(!overlaps(Movable{
+                type_object: MovableType::Player,
+                x: Coord { cell: 2, step: 300 },
+                y: Coord::at(3),
+                dir: Direction::None,
+                activation_time: 0,
+                previous_dir: Direction::None,
+                owner: 0,
             }, 
-            super::Movable{
-                type_object: super::MovableType::Player,
-                x: 4.0 * (super::BLOCK_SIZE as f64) + 1.0,
-                y: 3.0 * (super::BLOCK_SIZE as f64),
-                dir: super::Direction::None,
-                activation_time: std::time::Instant::now(),
-                previous_dir: super::Direction::None,
-            }), 
-            false);
-
-            assert_eq!(
-                super::overlaps(super::Movable{
-                type_object: super::MovableType::Player,
-                x: 2.0 * (super::BLOCK_SIZE as f64) + 15.0,
-                y: 7.0 * (super::BLOCK_SIZE as f64),
-                dir: super::Direction::None,
-                activation_time: std::time::Instant::now(),
-                previous_dir: super::Direction::None,
+            Movable{
+                type_object: MovableType::Player,
+                x: Coord { cell: 4, step: 20 },
+                y: Coord::at(3),
+                dir: Direction::None,
+                activation_time: 0,
+                previous_dir: Direction::None,
+                owner: 0,
+            }));
+
+            assert!(overlaps(Movable{
+                type_object: MovableType::Player,
+                x: Coord { cell: 2, step: 300 },
+                y: Coord::at(7),
+                dir: Direction::None,
+                activation_time: 0,
+                previous_dir: Direction::None,
+                owner: 0,
             }, 
-                super::Movable{
-                type_object: super::MovableType::Player,
-                x: 3.0 * (super::BLOCK_SIZE as f64) + 1.0,
-                y: 7.0 * (super::BLOCK_SIZE as f64),
-                dir: super::Direction::None,
-                activation_time: std::time::Instant::now(),
-                previous_dir: super::Direction::None,
-            }), 
-            true);
-
-            assert_eq!(
-                super::overlaps(super::Movable{
-                type_object: super::MovableType::Player,
-                x: 4.0 * (super::BLOCK_SIZE as f64),
-                y: 7.0 * (super::BLOCK_SIZE as f64) + 6.0,
-                dir: super::Direction::None,
-                activation_time: std::time::Instant::now(),
-                previous_dir: super::Direction::None,
+                Movable{
+                type_object: MovableType::Player,
+                x: Coord { cell: 3, step: 20 },
+                y: Coord::at(7),
+                dir: Direction::None,
+                activation_time: 0,
+                previous_dir: Direction::None,
+                owner: 0,
+            }));
+
+            assert!(overlaps(Movable{
+                type_object: MovableType::Player,
+                x: Coord::at(4),
+                y: Coord { cell: 7, step: 120 },
+                dir: Direction::None,
+                activation_time: 0,
+                previous_dir: Direction::None,
+                owner: 0,
             }, 
-                super::Movable{
-                type_object: super::MovableType::Player,
-                x: 4.0 * (super::BLOCK_SIZE as f64),
-                y: 7.0 * (super::BLOCK_SIZE as f64) - 11.0,
-                dir: super::Direction::None,
-                activation_time: std::time::Instant::now(),
-                previous_dir: super::Direction::None,
-            }), 
-            true);
+                Movable{
+                type_object: MovableType::Player,
+                x: Coord::at(4),
+                y: Coord { cell: 6, step: 380 },
+                dir: Direction::None,
+                activation_time: 0,
+                previous_dir: Direction::None,
+                owner: 0,
+            }));
+    }
+    #[test]
+    fn test_sub_cell_positions() {
+        assert_eq!(Coord::at(2).advance(-(MONSTER_SPEED as i64)), Coord { cell: 1, step: STEPS_PER_CELL - MONSTER_SPEED });
+        assert_eq!(Coord { cell: 1, step: 590 }.advance(25), Coord { cell: 2, step: 15 });
+        assert_eq!(Coord::at(0).advance(-(SHOT_SPEED as i64)), Coord::at(0));
+        assert_eq!(Coord { cell: 1, step: 300 }.pixels(), 45.0);
+
+        let mut monster = vec![Movable {
+            type_object: MovableType::Monster,
+            x: Coord::at(3),
+            y: Coord::at(5),
+            dir: Direction::Left,
+            activation_time: 0,
+            previous_dir: Direction::None,
+            owner: 0,
+        }];
+        for _ in 0..STEPS_PER_CELL / MONSTER_SPEED {
+            monster = move_object(monster);
+        }
+        assert_eq!((monster[0].x, monster[0].y), (Coord::at(3), Coord::at(4)));
+        assert!(monster[0].y.is_aligned());
+
+        let walls = parse_world("digger-level 1\n[grid]\n#..P\n*##S\n").immovable;
+        let mut shot = Movable { type_object: MovableType::Shot, x: Coord::at(0), y: Coord::at(2), ..monster[0] };
+        while !bag_or_shot_crashed(shot, &walls) {
+            shot = move_object(vec![shot])[0];
+        }
+        assert_eq!(shot.y.cell, 1);
+        assert!(shot.y.step < SHOT_SPEED);
     }
+
     #[test]
     fn test_moving() {
         let input = vec![
-            super::Movable {
-                type_object: super::MovableType::Player,
-                x: 4.0 * (super::BLOCK_SIZE as f64),
-                y: 7.0 * (super::BLOCK_SIZE as f64),
-                dir: super::Direction::None,
-                activation_time: std::time::Instant::now(),
-                previous_dir: super::Direction::None,
+            Movable {
+                type_object: MovableType::Player,
+                x: Coord::at(4),
+                y: Coord::at(7),
+                dir: Direction::None,
+                activation_time: 0,
+                previous_dir: Direction::None,
+                owner: 0,
             },
-            super::Movable{
-                type_object: super::MovableType::Monster,
-                x: 5.0 * (super::BLOCK_SIZE as f64),
-                y: 2.0 * (super::BLOCK_SIZE as f64),
-                dir: super::Direction::None,
-                activation_time: std::time::Instant::now(),
-                previous_dir: super::Direction::None,
+            Movable{
+                type_object: MovableType::Monster,
+                x: Coord::at(5),
+                y: Coord::at(2),
+                dir: Direction::None,
+                activation_time: 0,
+                previous_dir: Direction::None,
+                owner: 0,
             }
         ];
-        assert_eq!(super::move_object(input.clone()),input);
+        assert_eq!(move_object(input.clone()),input);
 
-        let now = std::time::Instant::now();
+        let now = 0;
         let input1 = vec![
-            super::Movable {
-                type_object: super::MovableType::Player,
-                x: 4.0 * (super::BLOCK_SIZE as f64),
-                y: 7.0 * (super::BLOCK_SIZE as f64),
-                dir: super::Direction::Right,
+            Movable {
+                type_object: MovableType::Player,
+                x: Coord::at(4),
+                y: Coord::at(7),
+                dir: Direction::Right,
                 activation_time: now,
-                previous_dir: super::Direction::None,
+                previous_dir: Direction::None,
+                owner: 0,
             },
-            super::Movable{
-                type_object: super::MovableType::Monster,
-                x: 5.0 * (super::BLOCK_SIZE as f64),
-                y: 2.0 * (super::BLOCK_SIZE as f64),
-                dir: super::Direction::Up,
+            Movable{
+                type_object: MovableType::Monster,
+                x: Coord::at(5),
+                y: Coord::at(2),
+                dir: Direction::Up,
                 activation_time: now,
-                previous_dir: super::Direction::None,
+                previous_dir: Direction::None,
+                owner: 0,
             }
         ];
         let output1 = vec![
-            super::Movable {
-                type_object: super::MovableType::Player,
-                x: 4.0 * (super::BLOCK_SIZE as f64),
-                y: 7.0 * (super::BLOCK_SIZE as f64) + (super::BLOCK_SIZE as f64),
-                dir: super::Direction::Right,
+            Movable {
+                type_object: MovableType::Player,
+                x: Coord::at(4),
+                y: Coord::at(7),
+                dir: Direction::Right,
                 activation_time: now,
-                previous_dir: super::Direction::None,
+                previous_dir: Direction::None,
+                owner: 0,
             },
-            super::Movable{
-                type_object: super::MovableType::Monster,
-                x: 5.0 * (super::BLOCK_SIZE as f64) - super::MONSTER_SPEED,
-                y: 2.0 * (super::BLOCK_SIZE as f64),
-                dir: super::Direction::Up,
+            Movable{
+                type_object: MovableType::Monster,
+                x: Coord { cell: 4, step: STEPS_PER_CELL - MONSTER_SPEED },
+                y: Coord::at(2),
+                dir: Direction::Up,
                 activation_time: now,
-                previous_dir: super::Direction::None,
+                previous_dir: Direction::None,
+                owner: 0,
             }
         ];
-        assert_eq!(super::move_object(input1),output1);
+        assert_eq!(move_object(input1),output1);
 
         let input2 = vec![
-            super::Movable {
-                type_object: super::MovableType::FallingBag,
-                x: 13.0 * (super::BLOCK_SIZE as f64),
-                y: 17.0 * (super::BLOCK_SIZE as f64),
-                dir: super::Direction::Down,
+            Movable {
+                type_object: MovableType::FallingBag,
+                x: Coord::at(13),
+                y: Coord::at(17),
+                dir: Direction::Down,
                 activation_time: now,
-                previous_dir: super::Direction::None,
+                previous_dir: Direction::None,
+                owner: 0,
             },
-            super::Movable{
-                type_object: super::MovableType::Shot,
-                x: 15.0 * (super::BLOCK_SIZE as f64),
-                y: 12.0 * (super::BLOCK_SIZE as f64),
-                dir: super::Direction::Left,
+            Movable{
+                type_object: MovableType::Shot,
+                x: Coord::at(15),
+                y: Coord::at(12),
+                dir: Direction::Left,
                 activation_time: now,
-                previous_dir: super::Direction::None,
+                previous_dir: Direction::None,
+                owner: 0,
             }
         ];
         let output2 = vec![
-            super::Movable {
-                type_object: super::MovableType::FallingBag,
-                x: 13.0 * (super::BLOCK_SIZE as f64) + super::BAG_SPEED,
-                y: 17.0 * (super::BLOCK_SIZE as f64),
-                dir: super::Direction::Down,
+            Movable {
+                type_object: MovableType::FallingBag,
+                x: Coord { cell: 13, step: BAG_SPEED },
+                y: Coord::at(17),
+                dir: Direction::Down,
                 activation_time: now,
-                previous_dir: super::Direction::None,
+                previous_dir: Direction::None,
+                owner: 0,
             },
-            super::Movable{
-                type_object: super::MovableType::Shot,
-                x: 15.0 * (super::BLOCK_SIZE as f64),
-                y: 12.0 * (super::BLOCK_SIZE as f64) - super::SHOT_SPEED,
-                dir: super::Direction::Left,
+            Movable{
+                type_object: MovableType::Shot,
+                x: Coord::at(15),
+                y: Coord { cell: 11, step: STEPS_PER_CELL - SHOT_SPEED },
+                dir: Direction::Left,
                 activation_time: now,
-                previous_dir: super::Direction::None,
+                previous_dir: Direction::None,
+                owner: 0,
             }
         ];
-        assert_eq!(super::move_object(input2),output2);
+        assert_eq!(move_object(input2),output2);
+    }
+
+    fn parse_world(map: &str) -> World {
+        World::from_level(&Level::parse(map).unwrap(), 0)
+    }
+
+    fn run(world: &mut World, clock: &mut dyn Clock, commands: &[Command]) -> Result<u64, RuntimeError> {
+        let due = clock.ticks_due();
+        let mut commands = commands;
+        for _ in 0..due {
+            world.step(commands)?;
+            commands = &[];
+        }
+        Ok(due)
+    }
+
+    /// Taps `command` and steps until the player has moved a whole cell.
+    fn walk(world: &mut World, command: Command) -> Result<(), RuntimeError> {
+        world.step(&[command, Command::Stop])?;
+        while !world.movable[world.player_pos(0)].x.is_aligned() || !world.movable[world.player_pos(0)].y.is_aligned() {
+            world.step(&[])?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_smooth_movement() {
+        let mut world = parse_world("digger-level 1\n[grid]\nP....\n.####\n*###S\n");
+        let position = |w: &World| (w.movable[w.player_pos(0)].x, w.movable[w.player_pos(0)].y);
+        assert_eq!(world.step(&[Command::Right]), Ok(()));
+        assert_eq!(position(&world), (Coord::at(0), Coord { cell: 0, step: 200 }));
+        assert_eq!(world.step(&[Command::Down]), Ok(()));
+        assert_eq!(world.step(&[Command::Stop]), Ok(()));
+        assert_eq!(position(&world), (Coord::at(0), Coord::at(1)));
+        assert_eq!(world.step(&[]), Ok(()));
+        assert_eq!(position(&world), (Coord { cell: 0, step: 200 }, Coord::at(1)));
+        assert_eq!(world.step(&[Command::Up]), Ok(()));
+        assert_eq!(world.step(&[]), Ok(()));
+        assert_eq!(position(&world), (Coord::at(0), Coord::at(1)));
+
+        assert_eq!(world.step(&[Command::Right]), Ok(()));
+        for _ in 0..5 {
+            assert_eq!(world.step(&[]), Ok(()));
+        }
+        assert_eq!(position(&world), (Coord::at(0), Coord::at(3)));
+        assert_eq!(world.movable[world.player_pos(0)].previous_dir, Direction::Right);
+
+        let level = Level::parse("digger-level 1\n[meta]\nplayer_speed = 18\n[grid]\nP..S\n***#\n").unwrap();
+        let mut world = World::from_level(&level, 0);
+        assert_eq!(world.step(&[Command::Right]), Ok(()));
+        assert_eq!(position(&world), (Coord::at(0), Coord::at(1)));
+        assert!(matches!(Level::parse("digger-level 1\n[meta]\nplayer_speed = 0\n[grid]\nPS\n"), Err(MapError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_emerald_pickup() {
+        let mut world = parse_world("player,diamond,diamond,\nspace,wall,spawn,");
+        assert_eq!(world.emeralds_left(), 2);
+        assert_eq!(world.step(&[Command::Right]), Ok(()));
+        assert_eq!(world.players[0].score, 20);
+        assert_eq!(world.immovable[0][1].type_object, ImmovableType::Background);
+        assert_eq!(world.emeralds_left(), 1);
+    }
+
+    #[test]
+    fn test_bag_falls() {
+        let mut world = parse_world("space,gold,diamond,wall,spawn,\nspace,player,diamond,wall,wall,\nspace,space,space,wall,wall,\nspace,space,space,wall,wall,");
+        let mut clock = ManualClock::new();
+        clock.advance(1);
+        assert_eq!(run(&mut world, &mut clock, &[]), Ok(1));
+        assert_eq!(world.immovable[0][1].type_object, ImmovableType::Background);
+        assert!(world.movable.iter().any(|m| m.type_object == MovableType::FallingBag && m.dir == Direction::None));
+
+        clock.advance(TIME_TO_FALL - 1);
+        assert_eq!(run(&mut world, &mut clock, &[Command::Left]), Ok(TIME_TO_FALL - 1));
+        assert!(world.movable.iter().all(|m| m.type_object != MovableType::FallingBag || m.dir == Direction::None));
+
+        clock.advance(1);
+        assert_eq!(run(&mut world, &mut clock, &[]), Ok(1));
+        assert!(world.movable.iter().any(|m| m.type_object == MovableType::FallingBag && m.dir == Direction::Down));
+
+        clock.advance(200);
+        assert_eq!(run(&mut world, &mut clock, &[]), Ok(200));
+        assert!(world.movable.iter().any(|m| m.type_object == MovableType::Crashed));
+    }
+
+    #[test]
+    fn test_bag_fall_height() {
+        let mut world = parse_world("digger-level 1\n[grid]\n$##S\n.###\n####\nP*##\n");
+        assert_eq!(world.step(&[]), Ok(()));
+        assert!(world.movable.iter().any(|m| m.type_object == MovableType::FallingBag && m.dir == Direction::None));
+        for _ in 0..TIME_TO_FALL + 2 * (STEPS_PER_CELL / BAG_SPEED) as u64 + 10 {
+            assert_eq!(world.step(&[]), Ok(()));
+        }
+        assert!(world.movable.iter().all(|m| m.type_object != MovableType::FallingBag && m.type_object != MovableType::Crashed));
+        assert_eq!(world.immovable[0][0].type_object, ImmovableType::Background);
+        assert_eq!(world.immovable[1][0].type_object, ImmovableType::Bag);
+
+        let mut world = parse_world("digger-level 1\n[grid]\n$##S\n.###\n.###\n####\nP*##\n");
+        for _ in 0..TIME_TO_FALL + 4 * (STEPS_PER_CELL / BAG_SPEED) as u64 + 10 {
+            assert_eq!(world.step(&[]), Ok(()));
+        }
+        assert!(world.movable.iter().any(|m| m.type_object == MovableType::Crashed && m.x == Coord::at(2)));
+        assert_eq!(world.immovable[2][0].type_object, ImmovableType::Background);
+
+        let mut world = parse_world("digger-level 1\n[grid]\n$##S\n.###\n.###\n####\nP*##\n");
+        let below = Movable { x: Coord::at(1), y: Coord::at(0), ..world.movable[world.player_pos(0)] };
+        world.movable.push(Movable { type_object: MovableType::Monster, owner: 0, ..below });
+        for _ in 0..TIME_TO_FALL + 4 * (STEPS_PER_CELL / BAG_SPEED) as u64 + 10 {
+            assert_eq!(world.step(&[]), Ok(()));
+        }
+        assert_eq!(world.kills, 1);
+        assert!(world.movable.iter().any(|m| m.type_object == MovableType::Crashed && m.x == Coord::at(2)));
+
+        let mut world = parse_world("digger-level 1\n[grid]\nP.$#S\n##.##\n*####\n");
+        assert_eq!(walk(&mut world, Command::Right), Ok(()));
+        assert_eq!(walk(&mut world, Command::Right), Ok(()));
+        assert_eq!(world.movable[world.player_pos(0)].y, Coord::at(1));
+        assert!(!world.is_dying());
+        assert_eq!(world.players[0].lives, 3);
+    }
+
+    #[test]
+    fn test_gold() {
+        let map = "[grid]\n$##S\n.###\n.###\n####\nP*##\n";
+        for (meta, eaten, shots) in [("", false, 1), ("gold_recharges = no\n", false, 0), ("monsters_eat_gold = yes\n", true, 0)].iter() {
+            let mut world = parse_world(&format!("digger-level 1\n[meta]\n{}{}", meta, map));
+            for _ in 0..TIME_TO_FALL + 4 * (STEPS_PER_CELL / BAG_SPEED) as u64 + 10 {
+                assert_eq!(world.step(&[]), Ok(()));
+            }
+            let gold = *world.movable.iter().find(|m| m.type_object == MovableType::Crashed).unwrap();
+            world.movable.push(Movable { type_object: MovableType::Monster, ..gold });
+            assert_eq!(world.step(&[]), Ok(()));
+            assert_eq!(world.movable.iter().all(|m| m.type_object != MovableType::Crashed), *eaten, "{}", meta);
+            world.movable.retain(|m| m.type_object != MovableType::Monster || m.x != gold.x || m.y != gold.y);
+
+            assert_eq!(walk(&mut world, Command::Up), Ok(()));
+            assert_eq!(walk(&mut world, Command::Up), Ok(()));
+            assert_eq!(world.players[0].score, if *eaten { 0 } else { 500 }, "{}", meta);
+            assert_eq!(world.players[0].shots, *shots, "{}", meta);
+        }
+    }
+
+    #[test]
+    fn test_push_bag() {
+        let mut world = parse_world("digger-level 1\n[grid]\nP$.#S\n#####\n*####\n");
+        assert_eq!(walk(&mut world, Command::Right), Ok(()));
+        assert_eq!(world.movable[world.player_pos(0)].y, Coord::at(1));
+        assert_eq!(world.immovable[0][1].type_object, ImmovableType::Background);
+        assert_eq!(world.immovable[0][2].type_object, ImmovableType::Bag);
+        assert_eq!(walk(&mut world, Command::Right), Ok(()));
+        assert_eq!(world.movable[world.player_pos(0)].y, Coord::at(1));
+        assert_eq!(world.immovable[0][2].type_object, ImmovableType::Bag);
+
+        for map in ["P$$.S\n#####\n*####\n", "#S#P$\n#####\n*####\n"].iter() {
+            let mut world = parse_world(&format!("digger-level 1\n[grid]\n{}", map));
+            let start = world.movable[world.player_pos(0)].y;
+            assert_eq!(walk(&mut world, Command::Right), Ok(()));
+            assert_eq!(world.movable[world.player_pos(0)].y, start, "{}", map);
+        }
+
+        let mut world = parse_world("digger-level 1\n[grid]\nP$.#S\n#####\n*####\n");
+        let player = world.movable[world.player_pos(0)];
+        world.movable.push(Movable { type_object: MovableType::Monster, y: Coord::at(2), ..player });
+        assert_eq!(walk(&mut world, Command::Right), Ok(()));
+        assert_eq!(world.movable[world.player_pos(0)].y, Coord::at(0));
+        assert_eq!(world.immovable[0][1].type_object, ImmovableType::Bag);
+        assert_eq!(world.immovable[0][2].type_object, ImmovableType::Background);
+
+        for (lives, pushed) in [(1, false), (0, true)].iter() {
+            let mut world = parse_world("digger-level 1\n[grid]\nP$P#S\n#####\n*####\n");
+            world.players[1].lives = *lives;
+            assert_eq!(walk(&mut world, Command::Right), Ok(()));
+            assert_eq!(world.immovable[0][2].type_object == ImmovableType::Bag, *pushed, "{}", lives);
+        }
+
+        let mut world = parse_world("digger-level 1\n[grid]\nP$.#S\n##.##\n*####\n");
+        assert_eq!(walk(&mut world, Command::Right), Ok(()));
+        assert_eq!(world.immovable[0][2].type_object, ImmovableType::Background);
+        assert!(world.movable.iter().any(|m| m.type_object == MovableType::FallingBag && m.y == Coord::at(2)));
+    }
+
+    #[test]
+    fn test_monster_killed_by_shot() {
+        let mut world = parse_world("player,space,space,spawn,\ndiamond,diamond,wall,wall,");
+        world.players[0].shots = 1;
+        assert_eq!(world.step(&[Command::Fire]), Ok(()));
+        assert_eq!(world.players[0].shots, 0);
+        for _ in 0..40 {
+            assert_eq!(world.step(&[]), Ok(()));
+        }
+        assert!(!world.movable.iter().any(|m| m.type_object == MovableType::Monster));
+        assert_eq!(world.players[0].score, ScoringRules::default().monster_shot);
+        assert_eq!(world.kills, 1);
+    }
+
+    #[test]
+    fn test_objectives() {
+        assert_eq!(Objective::parse_list("emeralds, kills 2"), Ok(vec![Objective::CollectEmeralds, Objective::KillMonsters(2)]));
+        assert!(Objective::parse_list("kills").is_err());
+        assert!(Objective::parse_list("survive 5 minutes").is_err());
+
+        let mut world = parse_world("digger-level 1\n[meta]\nobjective = exit\n[grid]\nP.E*#\n####S\n");
+        assert_eq!(walk(&mut world, Command::Right), Ok(()));
+        assert_eq!(walk(&mut world, Command::Right), Err(RuntimeError::Victory));
+        assert_eq!(world.emeralds_left(), 1);
+
+        let mut world = parse_world("digger-level 1\n[meta]\nobjective = survive 1, emeralds\n[grid]\nP*..#\n####S\n");
+        assert_eq!(world.step(&[Command::Right, Command::Stop]), Ok(()));
+        for _ in 2..TICKS_PER_SECOND {
+            assert_eq!(world.step(&[]), Ok(()));
+        }
+        assert_eq!(world.step(&[]), Err(RuntimeError::Victory));
+
+        let level = Level::parse("digger-level 1\n[meta]\nobjective = exit\n[grid]\nP.*#\n###S\n").unwrap();
+        assert!(validate(&level).iter().any(|problem| problem.severity == Severity::Error && problem.message.contains("exit")));
+    }
+
+    #[test]
+    fn test_scoring() {
+        let mut scoring = Scoring::new(ScoringRules::default());
+        let points = (1..=8).map(|tick| scoring.points(ScoreEvent::Emerald, tick)).collect::<Vec<usize>>();
+        assert_eq!(points, vec![20, 20, 20, 20, 20, 20, 20, 270]);
+        assert_eq!(scoring.points(ScoreEvent::Emerald, 9), 20);
+        let late = 9 + TICKS_PER_SECOND + 1;
+        let points = (late..late + 8).map(|tick| scoring.points(ScoreEvent::Emerald, tick)).collect::<Vec<usize>>();
+        assert_eq!(points.iter().sum::<usize>(), 8 * 20 + 250);
+        assert_eq!(scoring.points(ScoreEvent::MonsterCrushed, 0), 500);
+        assert_eq!(scoring.extra_lives(19990, 20010), 1);
+        assert_eq!(scoring.extra_lives(100, 39990), 1);
+
+        let mut world = parse_world("digger-level 1\n[meta]\nlives = 1\nextra_life_every = 40\nscore_emerald = 20\n[grid]\nP***#\n####S\n");
+        assert_eq!(walk(&mut world, Command::Right), Ok(()));
+        assert_eq!(world.players[0].lives, 1);
+        assert_eq!(walk(&mut world, Command::Right), Ok(()));
+        assert_eq!((world.players[0].score, world.players[0].lives), (40, 2));
+    }
+
+    #[test]
+    fn test_hobbins() {
+        let grid = "[grid]\nP##S\n*###\n";
+        let mut world = parse_world(&format!("digger-level 1\n{}", grid));
+        assert_eq!(world.step(&[]), Ok(()));
+        assert_eq!(world.immovable[0][2].type_object, ImmovableType::Wall);
+
+        let mut world = parse_world(&format!("digger-level 1\n[meta]\nmonsters = hobbin, nobbin\n{}", grid));
+        for _ in 0..10 {
+            assert_eq!(world.step(&[]), Ok(()));
+        }
+        assert_eq!(world.immovable[0][2].type_object, ImmovableType::Background);
+        let hobbin = *world.movable.iter().find(|m| m.type_object == MovableType::Hobbin).unwrap();
+        assert_eq!((hobbin.dir, hobbin.y), (Direction::Left, Coord { cell: 2, step: STEPS_PER_CELL - 9 * HOBBIN_SPEED }));
+
+        let mut world = parse_world(&format!("digger-level 1\n[meta]\nhobbin_after = 1\n{}", grid));
+        for _ in 1..TICKS_PER_SECOND {
+            assert_eq!(world.step(&[]), Ok(()));
+        }
+        assert!(world.movable.iter().any(|m| m.type_object == MovableType::Monster));
+        assert_eq!(world.step(&[]), Ok(()));
+        assert!(world.movable.iter().any(|m| m.type_object == MovableType::Hobbin));
+
+        let mut world = parse_world(&format!("digger-level 1\n[meta]\nmonsters = nobbin, hobbin\nspawn_interval = 1\n{}", grid));
+        while !world.movable.iter().any(|m| m.type_object == MovableType::Hobbin) {
+            assert_eq!(world.step(&[]), Ok(()));
+            assert!(world.tick <= TICKS_PER_SECOND);
+        }
+        let hobbin = *world.movable.iter().find(|m| m.type_object == MovableType::Hobbin).unwrap();
+        assert_eq!(hobbin.dir, Direction::Left);
+        assert!(matches!(Level::parse("digger-level 1\n[meta]\nmonsters = goblin\n[grid]\nP*S\n"), Err(MapError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_bonus() {
+        let mut world = parse_world("digger-level 1\n[meta]\nbonus_after = 1\nbonus_time = 5\n[grid]\nP..S\n**##\n");
+        world.players[0].shots = 1;
+        assert_eq!(world.step(&[Command::Fire]), Ok(()));
+        while world.kills == 0 {
+            assert_eq!(world.step(&[]), Ok(()));
+            assert!(world.tick < 50);
+        }
+        assert_eq!(world.immovable[0][3].type_object, ImmovableType::Bonus);
+        assert_eq!(world.step(&[Command::Right]), Ok(()));
+        while !world.is_bonus_active() {
+            assert_eq!(world.step(&[]), Ok(()));
+            assert!(world.tick < 100);
+        }
+        assert_eq!(world.bonus_left(), 5 * TICKS_PER_SECOND);
+
+        while world.kills < 2 {
+            assert_eq!(world.step(&[]), Ok(()));
+            assert!(world.is_bonus_active());
+        }
+        assert_eq!(world.players[0].score, 250 + 200);
+        assert_eq!(world.players[0].lives, 3);
+
+        let mut scoring = Scoring::new(ScoringRules::default());
+        let eaten = (0..3).map(|_| scoring.points(ScoreEvent::MonsterEaten, 0)).collect::<Vec<usize>>();
+        assert_eq!(eaten, vec![200, 400, 800]);
+
+        let mut world = parse_world("digger-level 1\n[grid]\nP..S.\n*####\n");
+        assert_eq!(world.step(&[]), Ok(()));
+        assert_eq!(world.movable.iter().find(|m| m.type_object == MovableType::Monster).unwrap().dir, Direction::Left);
+        world.bonus_until = 100;
+        assert_eq!(world.step(&[]), Ok(()));
+        assert_eq!(world.movable.iter().find(|m| m.type_object == MovableType::Monster).unwrap().dir, Direction::Right);
+    }
+
+    #[test]
+    fn test_monster_kills_player() {
+        let mut world = parse_world("player,space,spawn,\ndiamond,diamond,wall,");
+        world.players[0].lives = 1;
+        let mut result = Ok(());
+        for _ in 0..200 {
+            result = world.step(&[]);
+            if result.is_err() {
+                break;
+            }
+        }
+        assert_eq!(result, Err(RuntimeError::PlayerDead));
+
+        let mut world = parse_world("digger-level 1\n[grid]\n.S*\n..#\n.P#\n");
+        world.players[0].invulnerable_until = u64::MAX;
+        for _ in 0..200 {
+            assert_eq!(world.step(&[]), Ok(()));
+        }
+        let monster = *world.movable.iter().find(|m| m.type_object == MovableType::Monster).unwrap();
+        assert_eq!((monster.x, monster.y, monster.dir), (Coord::at(2), Coord::at(1), Direction::None));
+    }
+
+    #[test]
+    fn test_monster_spawn_timing() {
+        let mut world = parse_world("player,diamond,diamond,\nwall,wall,spawn,");
+        let monsters = |w: &World| w.movable.iter().filter(|m| m.type_object == MovableType::Monster).count();
+        for _ in 0..TIME_TO_SPAWN - 1 {
+            assert_eq!(world.step(&[]), Ok(()));
+        }
+        assert_eq!(monsters(&world), 1);
+        assert_eq!(world.step(&[]), Ok(()));
+        assert_eq!(monsters(&world), 2);
+    }
+
+    #[test]
+    fn test_spawn_schedule() {
+        let map = "digger-level 1\n[meta]\ntotal_monsters = 2\nspawn_interval = 1\n[grid]\nP*.*#\n#S#S#\n";
+        let mut world = parse_world(map);
+        let columns = |w: &World| w.movable.iter().filter(|m| m.type_object.is_monster()).map(|m| m.y.cell).collect::<Vec<usize>>();
+        assert_eq!(columns(&world), vec![1]);
+        for _ in 0..3 * TICKS_PER_SECOND {
+            assert_eq!(world.step(&[]), Ok(()));
+        }
+        assert_eq!(columns(&world), vec![1, 3]);
+        world.kills = 2;
+        assert_eq!(world.step(&[]), Ok(()));
+
+        let mut world = parse_world(&map.replace("[meta]\n", "[meta]\nobjective = monsters\n"));
+        assert_eq!(world.step(&[]), Ok(()));
+        world.kills = 2;
+        assert_eq!(world.step(&[]), Err(RuntimeError::Victory));
+        let level = Level::parse("digger-level 1\n[meta]\nobjective = monsters\n[grid]\nP*S\n").unwrap();
+        assert!(validate(&level).iter().any(|problem| problem.severity == Severity::Error && problem.message.contains("total_monsters")));
+
+        let level = Level::parse("digger-level 1\n[meta]\nspawn_order = random\nmax_monsters = 4\n[grid]\nP**#\nS#SS\n").unwrap();
+        assert_eq!(level.spawn.order, SpawnOrder::Random);
+        assert_eq!(level.spawn.interval, TIME_TO_SPAWN);
+        let level = Level::parse("digger-level 1\n[meta]\nspawn_order = random\nmax_monsters = 8\nspawn_interval = 1\n[grid]\nP**#\n####\nS#SS\n").unwrap();
+        let order = |seed: u64| {
+            let mut world = World::from_level(&level, seed);
+            let mut spawned = columns(&world);
+            while spawned.len() < 8 {
+                assert_eq!(world.step(&[]), Ok(()));
+                let now = columns(&world);
+                if now.len() > spawned.len() {
+                    spawned.push(*now.last().unwrap());
+                }
+            }
+            spawned
+        };
+        assert_eq!(order(1), order(1));
+        assert_ne!(order(1), order(2));
+        assert!(matches!(Level::parse("digger-level 1\n[meta]\nspawn_order = nearest\n[grid]\nPS\n"), Err(MapError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_replay_round_trip() {
+        let map = "player,space,space,diamond,\nspace,diamond,wall,wall,\nspace,space,space,spawn,";
+        let session = [(1, vec![Command::Right]), (4, vec![Command::Down, Command::Fire]), (9, vec![Command::Left])];
+
+        let mut recording = Replay::new(map_hash(map), 7);
+        let mut world = parse_world(map);
+        for tick in 1..40 {
+            let commands = vec![session.iter().find(|(t, _)| *t == tick).map_or(Vec::new(), |(_, c)| c.clone())];
+            recording.record(tick, &commands);
+            assert_eq!(world.step_players(&commands), Ok(()));
+        }
+
+        let mut playback = Replay::parse(&recording.to_text()).unwrap();
+        assert_eq!(playback.map_hash, map_hash(map));
+        assert_eq!(playback.seed, 7);
+        assert_eq!(playback.inputs, recording.inputs);
+
+        let mut replayed = parse_world(map);
+        for tick in 1..40 {
+            let commands = playback.commands_at(tick);
+            assert_eq!(replayed.step_players(&commands), Ok(()));
+        }
+        assert_eq!(replayed.movable, world.movable);
+        assert_eq!(replayed.immovable, world.immovable);
+        assert_eq!(replayed.players[0].score, world.players[0].score);
+
+        assert!(matches!(Replay::parse("digger-replay 0.0.0\nmap 0\nseed 0\n"), Err(ReplayError::VersionMismatch(_))));
+        assert!(matches!(Replay::parse(&format!("{}2 jump\n", Replay::new(0, 0).to_text())), Err(ReplayError::Malformed(4))));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_level_formats() {
+        let sectioned = "digger-level 1\n\n[meta]\nname = Tiny\nauthor = Someone\ntime_limit = 2\nmax_monsters = 5\n\n[legend]\n~ = space\n\n[grid]\nP~$*\n##*S\n";
+        let level = Level::parse(sectioned).unwrap();
+        assert_eq!(level.name, "Tiny");
+        assert_eq!(level.author, "Someone");
+        assert_eq!(level.time_limit, 2);
+        assert_eq!(level.spawn.max_concurrent, 5);
+        assert_eq!(level.lives, 3);
+        assert_eq!(level.cells, vec![
+            vec![Cell::Player, Cell::Space, Cell::Gold, Cell::Diamond],
+            vec![Cell::Wall, Cell::Wall, Cell::Diamond, Cell::Spawn],
+        ]);
+
+        let legacy = Level::parse("player,space,gold,diamond,\nwall,wall,diamond,spawn,").unwrap();
+        assert_eq!(legacy.cells, level.cells);
+        assert_eq!(legacy.spawn.max_concurrent, 3);
+
+        assert!(matches!(Level::parse("digger-level 2\n[grid]\nPS\n"), Err(MapError::Syntax { .. })));
+        assert!(matches!(Level::parse("digger-level 1\n[meta]\ncolour = red\n"), Err(MapError::Syntax { .. })));
+
+        let mut world = World::from_level(&level, 0);
+        assert_eq!(world.name, "Tiny");
+        let mut result = Ok(());
+        for _ in 0..3 * TICKS_PER_SECOND {
+            result = world.step(&[Command::Stop]);
+            if result.is_err() {
+                break;
+            }
+        }
+        assert_eq!(result, Err(RuntimeError::OutOfTime));
+        assert_eq!(world.tick, 2 * TICKS_PER_SECOND + 1);
+    }
+
+    #[test]
+    fn test_map_errors() {
+        let unknown = "player,space,spawn,\nwall,rock,wall,\n";
+        let error = Level::parse(unknown).unwrap_err();
+        assert!(matches!(error, MapError::UnknownToken { ref token, .. } if token == "rock"));
+        assert_eq!(error.location().map(|at| (at.line, at.column, at.width)), Some((2, 6, 4)));
+        assert_eq!(error.report(unknown), "error: unknown token `rock`\n --> :2:6\n  |\n2 | wall,rock,wall,\n  |      ^^^^");
+
+        let error = Level::parse("digger-level 1\n[grid]\nP*S\n*\n").unwrap_err();
+        assert!(matches!(error, MapError::RaggedRow { expected: 3, found: 1, .. }));
+        assert_eq!(error.location().map(|at| at.line), Some(4));
+
+        let error = Level::parse("digger-level 1\n\n[grid]\nP*S\n*xS\n").unwrap_err();
+        assert!(matches!(error, MapError::UnknownToken { ref token, .. } if token == "x"));
+        assert_eq!(error.location().map(|at| (at.line, at.column)), Some((5, 2)));
+
+        assert!(Level::parse("player,spawn,\nwall,player,\n").is_ok());
+        let error = Level::parse("player,spawn,\nwall,player,\nplayer,wall,\n").unwrap_err();
+        assert!(matches!(error, MapError::MultiplePlayers { .. }));
+        assert_eq!(error.location().map(|at| (at.line, at.column)), Some((3, 1)));
+
+        assert!(matches!(Level::parse("space,spawn,\n"), Err(MapError::MissingPlayer { .. })));
+        assert!(matches!(Level::parse("space,player,\n"), Err(MapError::MissingSpawn { .. })));
+        assert!(matches!(Level::parse("player,spawn"), Err(MapError::Syntax { .. })));
+        assert!(matches!(Level::parse("\n  \n"), Err(MapError::EmptyFile { .. })));
+        assert!(matches!(Level::parse("digger-level 1\n[meta]\nname = x\n"), Err(MapError::EmptyFile { .. })));
+
+        assert!(Level::load("map.txt").is_ok());
+        match Level::load("no-such-map.txt") {
+            Err(MapError::Io { path, .. }) => assert_eq!(path, "no-such-map.txt"),
+            _ => panic!("expected an I/O error"),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(validate(&Level::load("map.txt").unwrap()).iter().filter(|p| p.severity == Severity::Error).count(), 0);
+
+        let level = Level::parse("digger-level 1\n[grid]\n$P$#S\n*$##*\n").unwrap();
+        let problems = validate(&level);
+        let found = |severity, row, column| problems.iter().any(|p| p.severity == severity && p.row == row && p.column == column);
+        assert!(found(Severity::Error, 1, 2));
+        assert!(found(Severity::Error, 2, 1));
+        assert!(found(Severity::Error, 2, 5));
+        assert!(found(Severity::Error, 1, 5));
+        assert!(found(Severity::Warning, 2, 2));
+        assert!(found(Severity::Warning, 1, 1));
+
+        let problems = validate(&Level::parse("player,space,spawn,\n").unwrap());
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].severity, Severity::Error);
+        assert_eq!(problems[0].row, 0);
+    }
+
+    #[test]
+    fn test_campaign() {
+        let mut campaign = Campaign::parse("; two levels\ndigger-campaign 1\nname = Test\n\none.txt\ntwo.txt\n", std::path::Path::new("levels")).unwrap();
+        assert_eq!(campaign.name, "Test");
+        assert_eq!(campaign.levels.len(), 2);
+        assert!(campaign.current_path().ends_with("one.txt"));
+        assert!(campaign.advance());
+        assert_eq!(campaign.level_number(), 2);
+        assert!(campaign.current_path().ends_with("two.txt"));
+        assert!(!campaign.advance());
+
+        assert!(matches!(Campaign::parse("map.txt\n", std::path::Path::new("")), Err(CampaignError::Malformed { line: 1, .. })));
+        assert!(matches!(Campaign::parse("digger-campaign 1\n", std::path::Path::new("")), Err(CampaignError::Empty { .. })));
+
+        let campaign = Campaign::load("campaign.txt").unwrap();
+        for path in &campaign.levels {
+            assert!(Level::load(path).is_ok(), "{}", path);
+        }
+
+        let mut world = parse_world("player,diamond,diamond,wall,spawn,\nspace,wall,wall,wall,wall,");
+        world.carry(&[Progress { score: 100, shots: 2, lives: 1 }]);
+        assert_eq!(walk(&mut world, Command::Right), Ok(()));
+        assert_eq!(world.players[0].score, 120);
+        assert_eq!(walk(&mut world, Command::Right), Err(RuntimeError::Victory));
+        assert_eq!(world.progress(), vec![Progress { score: 140, shots: 2, lives: 1 }]);
+    }
+
+    #[test]
+    fn test_lives_and_respawn() {
+        let mut world = parse_world("digger-level 1\n[meta]\nlives = 2\n[grid]\nP..S\n**##\n");
+        assert_eq!(world.players[0].lives, 2);
+        let start = world.movable[world.player_pos(0)];
+
+        let mut ticks = 0;
+        while !world.is_dying() {
+            assert_eq!(world.step(&[]), Ok(()));
+            ticks += 1;
+            assert!(ticks < 200);
+        }
+        assert_eq!(world.players[0].lives, 1);
+
+        let frozen = world.movable.clone();
+        for _ in 1..DEATH_TICKS {
+            assert_eq!(world.step(&[Command::Down]), Ok(()));
+        }
+        assert_eq!(world.movable, frozen);
+
+        assert_eq!(world.step(&[]), Ok(()));
+        assert!(!world.is_dying());
+        assert!(world.is_invulnerable(0));
+        let player = world.movable[world.player_pos(0)];
+        assert_eq!((player.x, player.y), (start.x, start.y));
+        assert!(world.movable.iter().filter(|m| m.type_object == MovableType::Monster).all(|m| m.x == Coord::at(0) && m.y == Coord::at(3)));
+
+        let mut result = Ok(());
+        for _ in 0..INVULNERABLE_TICKS + 200 {
+            result = world.step(&[]);
+            if result.is_err() {
+                break;
+            }
+        }
+        assert_eq!(result, Err(RuntimeError::PlayerDead));
+        assert_eq!(world.players[0].lives, 0);
+        assert!(world.tick > INVULNERABLE_TICKS);
+    }
+
+    #[test]
+    fn test_coop() {
+        let mut world = parse_world("digger-level 1\n[meta]\nlives = 1\n[grid]\nP*..S.P\n******#\n");
+        assert_eq!(world.players.len(), 2);
+        assert_eq!(world.living_players(), vec![0, 1]);
+
+        assert_eq!(world.step_players(&[vec![Command::Right, Command::Stop], vec![]]), Ok(()));
+        while !world.movable[world.player_pos(0)].y.is_aligned() {
+            assert_eq!(world.step_players(&[]), Ok(()));
+        }
+        assert_eq!((world.players[0].score, world.players[1].score), (20, 0));
+        let monster = |w: &World| *w.movable.iter().find(|m| m.type_object == MovableType::Monster).unwrap();
+        assert_eq!(monster(&world).dir, Direction::Right);
+
+        let mut ticks = 0;
+        while !world.players[1].is_out() {
+            assert_eq!(world.step_players(&[]), Ok(()));
+            ticks += 1;
+            assert!(ticks < 200);
+        }
+        assert_eq!(world.living_players(), vec![0]);
+        assert_eq!(world.players[0].lives, 1);
+        for _ in 0..DEATH_TICKS + 2 {
+            assert_eq!(world.step_players(&[]), Ok(()));
+        }
+        assert_eq!(monster(&world).dir, Direction::Left);
+
+        let mut result = Ok(());
+        for _ in 0..INVULNERABLE_TICKS + 200 {
+            result = world.step_players(&[]);
+            if result.is_err() {
+                break;
+            }
+        }
+        assert_eq!(result, Err(RuntimeError::PlayerDead));
+        assert_eq!(world.progress().len(), 2);
+
+        let mut world = parse_world("digger-level 1\n[meta]\nlives = 2\n[grid]\nP*..S.P\n******#\n");
+        let start = world.movable[world.player_pos(1)];
+        while !world.players[1].is_dying() {
+            assert_eq!(world.step_players(&[]), Ok(()));
+            assert!(world.tick < 200);
+        }
+        assert!(!world.is_dying());
+        let corpse = world.movable[world.player_pos(1)];
+        for _ in 1..DEATH_TICKS {
+            assert_eq!(world.step_players(&[vec![Command::Down], vec![Command::Left]]), Ok(()));
+        }
+        assert_eq!(world.movable[world.player_pos(1)], corpse);
+        let survivor = world.movable[world.player_pos(0)];
+        assert_ne!(survivor.x, Coord::at(0));
+        assert_eq!(world.step_players(&[]), Ok(()));
+        let revived = world.movable[world.player_pos(1)];
+        assert_eq!((revived.x, revived.y), (start.x, start.y));
+        assert_eq!((world.players[1].lives, world.is_invulnerable(1), world.is_invulnerable(0)), (1, true, false));
+        assert_ne!(world.movable[world.player_pos(0)].x, Coord::at(0));
+
+        let source = "digger-controls 1\nfire = LCtrl\n[player 2]\nfire = RCtrl\n";
+        let first = Controls::parse(source, 0).unwrap();
+        let second = Controls::parse(source, 1).unwrap();
+        assert_eq!((first.actions(Trigger::Key(Key::LCtrl)), first.actions(Trigger::Key(Key::RCtrl))), (vec![Action::Fire], vec![]));
+        assert_eq!((second.actions(Trigger::Key(Key::RCtrl)), second.actions(Trigger::Key(Key::LCtrl))), (vec![Action::Fire], vec![]));
+        assert_eq!(second.actions(Trigger::Key(Key::I)), vec![Action::MoveUp]);
+        assert_eq!(second.actions(Trigger::Key(Key::W)), vec![]);
+        assert!(matches!(Controls::parse("digger-controls 1\n[player 3]\n", 0), Err(ControlsError::Malformed { line: 2 })));
+        assert_eq!(Controls::load("controls.txt", 1).unwrap().actions(Trigger::Key(Key::O)), vec![Action::Fire]);
+        let mut input = PlayerInput::new(second, 1);
+        assert!(input.event(&button(Button::Controller(ControllerButton::new(0, 6)), ButtonState::Press)).is_empty());
+        assert_eq!(input.event(&button(Button::Controller(ControllerButton::new(1, 6)), ButtonState::Press)), vec![Action::Pause]);
+
+        let mut replay = Replay::new(0, 0);
+        replay.record(3, &[vec![Command::Left], vec![Command::Fire]]);
+        let mut replay = Replay::parse(&replay.to_text()).unwrap();
+        assert_eq!(replay.commands_at(3), vec![vec![Command::Left], vec![Command::Fire]]);
+    }
+
+    fn button(button: Button, state: ButtonState) -> Event {
+        Event::Input(Input::Button(ButtonArgs { state, button, scancode: None }), None)
+    }
+
+    #[test]
+    fn test_controls() {
+        let controls = Controls::parse("digger-controls 1\n; arrows only\nmove_left = Left J\nfire = LCtrl\n", 0).unwrap();
+        assert_eq!(controls.actions(Trigger::Key(Key::J)), vec![Action::MoveLeft]);
+        assert_eq!(controls.actions(Trigger::Key(Key::A)), vec![]);
+        assert_eq!(controls.actions(Trigger::Key(Key::Space)), vec![]);
+        assert_eq!(controls.actions(Trigger::Key(Key::W)), vec![Action::MoveUp]);
+        assert_eq!(controls.key_name(Action::Fire), "LCtrl");
+        assert!(matches!(Controls::parse("digger-controls 1\njump = Space\n", 0), Err(ControlsError::UnknownAction { line: 2, .. })));
+        assert!(matches!(Controls::parse("digger-controls 1\nfire = Spacebar\n", 0), Err(ControlsError::UnknownKey { line: 2, .. })));
+        assert!(matches!(Controls::parse("fire = Space\n", 0), Err(ControlsError::Malformed { line: 1 })));
+        assert_eq!(Controls::load("no-such-controls.txt", 0).unwrap(), Controls::default());
+
+        let mut input = PlayerInput::new(controls, 0);
+        let keyboard = |key| Button::Keyboard(key);
+        assert!(input.event(&button(keyboard(Key::J), ButtonState::Press)).is_empty());
+        assert!(input.event(&button(keyboard(Key::Up), ButtonState::Press)).is_empty());
+        assert!(input.event(&button(keyboard(Key::Q), ButtonState::Press)).is_empty());
+        assert!(input.event(&button(keyboard(Key::Up), ButtonState::Release)).is_empty());
+        assert_eq!(input.take_commands(), vec![Command::Left, Command::Up, Command::Left]);
+        input.event(&button(keyboard(Key::J), ButtonState::Release));
+        input.event(&button(keyboard(Key::LCtrl), ButtonState::Press));
+        input.event(&button(keyboard(Key::LCtrl), ButtonState::Release));
+        assert_eq!(input.take_commands(), vec![Command::Stop, Command::Fire]);
+        assert_eq!(input.event(&button(keyboard(Key::P), ButtonState::Press)), vec![Action::Pause]);
+        assert_eq!(input.event(&button(keyboard(Key::Return), ButtonState::Press)), vec![Action::Confirm]);
+        assert!(input.take_commands().is_empty());
+    }
+
+    #[test]
+    fn test_gamepad() {
+        let axis = |axis, position| Event::Input(Input::Move(Motion::ControllerAxis(ControllerAxisArgs::new(0, axis, position))), None);
+        let hat = |state| button(Button::Hat(ControllerHat::new(0, 0, state)), ButtonState::Press);
+        let pad = |number, state| button(Button::Controller(ControllerButton::new(0, number)), state);
+
+        let mut input = PlayerInput::new(Controls::default(), 0);
+        for position in [-0.2, -0.8, -0.9, 0.1, 0.7, -0.7, 0.0].iter() {
+            input.event(&axis(0, *position));
+        }
+        assert_eq!(input.take_commands(), vec![Command::Left, Command::Stop, Command::Right, Command::Stop, Command::Left, Command::Stop]);
+
+        input.event(&hat(HatState::Up));
+        input.event(&hat(HatState::RightUp));
+        input.event(&hat(HatState::Centered));
+        assert_eq!(input.take_commands(), vec![Command::Up, Command::Right, Command::Right, Command::Stop]);
+
+        assert_eq!(input.event(&pad(0, ButtonState::Press)), vec![Action::Confirm]);
+        input.event(&pad(0, ButtonState::Release));
+        assert_eq!(input.take_commands(), vec![Command::Fire]);
+        assert_eq!(input.event(&pad(6, ButtonState::Press)), vec![Action::Pause]);
+
+        let controls = Controls::parse("digger-controls 1\ndead_zone = 0.9\nfire = pad2 Space\nmove_up = dpad_up axis3-\n", 0).unwrap();
+        assert_eq!(controls.actions(Trigger::Pad(2)), vec![Action::Fire]);
+        assert_eq!(controls.actions(Trigger::Pad(0)), vec![Action::Confirm]);
+        assert_eq!(controls.actions(Trigger::Axis(3, false)), vec![Action::MoveUp]);
+        assert_eq!(controls.key_name(Action::MoveUp), "dpad_up");
+        let mut input = PlayerInput::new(controls, 0);
+        input.event(&axis(3, -0.8));
+        assert!(input.take_commands().is_empty());
+        assert!(matches!(Controls::parse("digger-controls 1\ndead_zone = 2\n", 0), Err(ControlsError::Malformed { line: 2 })));
+        assert!(matches!(Controls::parse("digger-controls 1\nfire = axis1\n", 0), Err(ControlsError::UnknownKey { line: 2, .. })));
+    }
+
+    #[test]
+    fn test_session_states() {
+        let mut session = Session::new(Campaign::load("campaign.txt").unwrap(), 7).unwrap();
+        assert_eq!(session.state, GameState::Title);
+        session.step(&[]);
+        assert_eq!(session.world.tick, 0);
+        assert!(!session.confirm().unwrap());
+        assert_eq!(session.state, GameState::Playing);
+        session.toggle_pause();
+        session.step(&[]);
+        assert_eq!((session.state, session.world.tick), (GameState::Paused, 0));
+        session.toggle_pause();
+
+        session.world.time_limit = 5;
+        while session.state == GameState::Playing {
+            session.step(&[]);
+            assert!(session.world.tick < 10);
+        }
+        assert_eq!(session.state, GameState::GameOver);
+        assert!(session.confirm().unwrap());
+        assert_eq!((session.state, session.world.tick, session.world.seed), (GameState::Playing, 0, 7));
+        assert_eq!(session.campaign.level_number(), 1);
+
+        session.world.players[0].score = 100;
+        for cell in session.world.immovable.iter_mut().flatten() {
+            cell.type_object = ImmovableType::Background;
+        }
+        session.step(&[]);
+        assert_eq!(session.state, GameState::LevelComplete);
+        assert!(session.confirm().unwrap());
+        assert_eq!(session.campaign.level_number(), 2);
+        assert_eq!(session.world.players[0].score, 100);
+
+        let mut session = Session::new(Campaign::single("map.txt"), 7).unwrap();
+        session.confirm().unwrap();
+        for cell in session.world.immovable.iter_mut().flatten() {
+            cell.type_object = ImmovableType::Background;
+        }
+        session.step(&[]);
+        assert_eq!(session.state, GameState::Victory);
+        assert!(session.confirm().unwrap());
+        assert_eq!((session.state, session.world.players[0].score), (GameState::Playing, 0));
+
+        session.step(&[]);
+        session.toggle_pause();
+        assert!(session.restart().unwrap());
+        assert_eq!((session.state, session.world.tick), (GameState::Playing, 0));
+    }
+
+    #[test]
+    fn test_atlas() {
+        let atlas = Atlas::load("atlas.txt").unwrap();
+        for frames in atlas.sprites.values() {
+            assert!(std::path::Path::new(&frames.image).is_file(), "{}", frames.image);
+        }
+
+        let all = Sprite::ALL.iter().map(|sprite| format!("{} = sheet.png 0,0,30,30\n", sprite.name())).collect::<String>();
+        let atlas = Atlas::parse(&format!("digger-atlas 1\nfps = 6\n{}monster = sheet.png 0,0,30,30 30,0,30,30 60,0,30,30\n", all)).unwrap();
+        assert_eq!(atlas.frame(Sprite::Monster, 0), [0.0, 0.0, 30.0, 30.0]);
+        assert_eq!(atlas.frame(Sprite::Monster, TICKS_PER_SECOND / 6), [30.0, 0.0, 30.0, 30.0]);
+        assert_eq!(atlas.frame(Sprite::Monster, TICKS_PER_SECOND / 2), [0.0, 0.0, 30.0, 30.0]);
+        assert_eq!(atlas.frame(Sprite::Wall, 5), [0.0, 0.0, 30.0, 30.0]);
+
+        assert!(matches!(Atlas::parse("digger-atlas 1\nwall = wall.png 0,0,30\n"), Err(AtlasError::Malformed { line: 2 })));
+        assert!(matches!(Atlas::parse("digger-atlas 1\nladder = wall.png 0,0,30,30\n"), Err(AtlasError::UnknownSprite { line: 2, .. })));
+        assert!(matches!(Atlas::parse("digger-atlas 1\n"), Err(AtlasError::MissingSprite(Sprite::Background))));
+    }
+}
@@ -1,27 +1,85 @@
-use crate::game::{PLAYER_SPEED, MONSTER_SPEED, SHOT_SPEED, BLOCK_SIZE, BAG_SPEED};
+use crate::world::{MONSTER_SPEED, HOBBIN_SPEED, SHOT_SPEED, BLOCK_SIZE, BAG_SPEED, STEPS_PER_CELL};
 use crate::immovable_objects::*;
-use std::time::{Instant};
 
+/// Position along one axis: a cell index plus how many steps the object has moved on into the next cell.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Coord {
+    pub cell: usize,
+    /// Always below `STEPS_PER_CELL`.
+    pub step: u32,
+}
+
+impl Coord {
+    /// Exactly on cell `cell`.
+    pub fn at(cell: usize) -> Self {
+        Coord { cell, step: 0 }
+    }
+
+    fn steps(self) -> i64 {
+        self.cell as i64 * STEPS_PER_CELL as i64 + self.step as i64
+    }
+
+    /// Positions before the first cell are clamped to it.
+    fn from_steps(steps: i64) -> Self {
+        let steps = steps.max(0);
+        Coord {
+            cell: (steps / STEPS_PER_CELL as i64) as usize,
+            step: (steps % STEPS_PER_CELL as i64) as u32,
+        }
+    }
+
+    /// Moved `steps` forward, or backward if negative.
+    pub fn advance(self, steps: i64) -> Self {
+        Coord::from_steps(self.steps() + steps)
+    }
+
+    pub fn is_aligned(self) -> bool {
+        self.step == 0
+    }
+
+    /// Number of steps between the two positions.
+    pub fn distance(self, other: Coord) -> u32 {
+        (self.steps() - other.steps()).unsigned_abs() as u32
+    }
+
+    /// Pixel offset of the position, for drawing only.
+    pub fn pixels(self) -> f64 {
+        self.steps() as f64 * BLOCK_SIZE as f64 / STEPS_PER_CELL as f64
+    }
+}
+
+/// Something that moves. `x` is the row and `y` the column.
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Movable {
     pub type_object: MovableType,
-    pub x: f64,
-    pub y: f64,
+    pub x: Coord,
+    pub y: Coord,
     pub dir: Direction,
-    pub activation_time: std::time::Instant,
+    pub activation_time: u64,
     pub previous_dir: Direction,
+    /// Index of the player this belongs to: the player itself, whoever fired a shot, or whoever loosened a bag.
+    pub owner: usize,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum MovableType {
     Player,
+    /// A Nobbin, which only moves along tunnels.
     Monster,
+    /// Digs its own way through walls, but moves slower.
+    Hobbin,
     FallingBag,
     Shot,
     Crashed,
     Converted,
 }
 
+impl MovableType {
+    pub fn is_monster(self) -> bool {
+        self == MovableType::Monster || self == MovableType::Hobbin
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Direction {
     Up,
@@ -31,140 +89,128 @@ pub enum Direction {
     None,
 }
 
+impl Direction {
+    pub fn reverse(self) -> Direction {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+            Direction::None => Direction::None,
+        }
+    }
+}
+
+/// Moves `object` by `speed` steps in its direction.
+fn advance(object: &mut Movable, speed: u32) {
+    let speed = speed as i64;
+    match object.dir {
+        Direction::Up => object.x = object.x.advance(-speed),
+        Direction::Down => object.x = object.x.advance(speed),
+        Direction::Left => object.y = object.y.advance(-speed),
+        Direction::Right => object.y = object.y.advance(speed),
+        Direction::None => {}
+    }
+}
+
+/// Moves everything but the player, which `World` steers itself.
 pub fn move_object(input: Vec<Movable>) -> Vec<Movable> {
     let mut output = Vec::<Movable>::new();
-    for i in 0..input.len() {
-        output.push(input[i]);
-        match output[i].type_object {
-            MovableType::Player => {
-                match output[i].dir {
-                    Direction::Up => output[i].x -= PLAYER_SPEED,
-                    Direction::Down => output[i].x += PLAYER_SPEED,
-                    Direction::Left => output[i].y -= PLAYER_SPEED,
-                    Direction::Right => output[i].y += PLAYER_SPEED,
-                    _ => {}
-                }
-            },
-            MovableType::Monster => {
-                match output[i].dir {
-                    Direction::Up => output[i].x -= MONSTER_SPEED,
-                    Direction::Down => output[i].x += MONSTER_SPEED,
-                    Direction::Left => output[i].y -= MONSTER_SPEED,
-                    Direction::Right => output[i].y += MONSTER_SPEED,
-                    _ => {}
-                }
-            }
-            MovableType::FallingBag => {
-                if output[i].dir == Direction::Down {
-                    output[i].x += BAG_SPEED;
-                };
-            }
-            MovableType::Shot => {
-                match output[i].dir {
-                    Direction::Up => output[i].x -= SHOT_SPEED,
-                    Direction::Down => output[i].x += SHOT_SPEED,
-                    Direction::Left => output[i].y -= SHOT_SPEED,
-                    Direction::Right => output[i].y += SHOT_SPEED,
-                    _ => {}
-                }
-            }
+    for mut object in input {
+        match object.type_object {
+            MovableType::Monster => advance(&mut object, MONSTER_SPEED),
+            MovableType::Hobbin => advance(&mut object, HOBBIN_SPEED),
+            MovableType::FallingBag if object.dir == Direction::Down => advance(&mut object, BAG_SPEED),
+            MovableType::Shot => advance(&mut object, SHOT_SPEED),
             _ => {}
         }
+        output.push(object);
     }
     output
 }
 
-
-
+/// True when the two objects are in line on one axis and less than a cell apart on the other.
 pub fn overlaps(obj1: Movable, obj2: Movable) -> bool {
+    (obj1.x == obj2.x && obj1.y.distance(obj2.y) < STEPS_PER_CELL)
+    || (obj1.y == obj2.y && obj1.x.distance(obj2.x) < STEPS_PER_CELL)
+}
 
-    if obj1.x - obj2.x < 0.1 
-    && obj1.x - obj2.x > -0.1 
-    && obj1.y - obj2.y < BLOCK_SIZE as f64 
-    && obj1.y - obj2.y > -(BLOCK_SIZE as f64) {
-        return true;
-    }
-
-    if obj1.y - obj2.y < 0.1 
-    && obj1.y - obj2.y > -0.1 
-    && obj1.x - obj2.x < BLOCK_SIZE as f64 
-    && obj1.x - obj2.x > -(BLOCK_SIZE as f64) {
-        return true;
+/// True once a falling bag sits exactly on top of something solid or on the bottom edge.
+pub fn bag_landed(bag: Movable, walls: &[Vec<Immovable>]) -> bool {
+    if !bag.x.is_aligned() {
+        return false;
     }
-    
-    false
+    let (bag_x, bag_y) = (bag.x.cell, bag.y.cell);
+    bag_x + 1 >= walls.len() || walls[bag_x + 1][bag_y].type_object != ImmovableType::Background
 }
 
-pub fn bag_or_shot_crashed(obj: Movable, walls: Vec<Vec<Immovable>>) -> bool {
-    let obj_X = (obj.x / (BLOCK_SIZE as f64)) as usize;
-    let obj_Y = (obj.y / (BLOCK_SIZE as f64)) as usize;
+pub fn bag_or_shot_crashed(obj: Movable, walls: &[Vec<Immovable>]) -> bool {
+    let obj_x = obj.x.cell;
+    let obj_y = obj.y.cell;
+    // Going up or left, the object leaves its cell within a tick once it is less than a tick's movement from the edge.
+    let speed = if obj.type_object == MovableType::Shot { SHOT_SPEED } else { BAG_SPEED };
 
     match obj.dir {
         Direction::Up => {
-            if obj_X == 0 && obj.x < 0.1 {
+            if obj_x == 0 && obj.x.is_aligned() {
                 return true;
             }
-            if obj_X > 0 && 
-            (walls[obj_X - 1][obj_Y].type_object == ImmovableType::Bag
-            || walls[obj_X - 1][obj_Y].type_object == ImmovableType::Emerald
-            || walls[obj_X - 1][obj_Y].type_object == ImmovableType::Wall)
-            && obj.x % (BLOCK_SIZE as f64) < 1.0 {
-                return true;
-            }
-            return false;
+            obj_x > 0 &&
+            (walls[obj_x - 1][obj_y].type_object == ImmovableType::Bag
+            || walls[obj_x - 1][obj_y].type_object == ImmovableType::Emerald
+            || walls[obj_x - 1][obj_y].type_object == ImmovableType::Wall)
+            && obj.x.step < speed
         },
         Direction::Down => {
-            if obj_X >= walls.len() - 1 {
+            if obj_x >= walls.len() - 1 {
                 return true;
             }
-            if obj_X < walls.len() - 2 && walls[obj_X + 1][obj_Y].type_object == ImmovableType::Background {
+            if obj_x < walls.len() - 2 && walls[obj_x + 1][obj_y].type_object == ImmovableType::Background {
                 return false;
             }
-            if obj_X < walls.len() - 2 {
+            if obj_x < walls.len() - 2 {
                 return overlaps(obj, Movable{
                     type_object: MovableType::Converted,
-                    x: walls[obj_X + 1][obj_Y].x,
-                    y: walls[obj_X + 1][obj_Y].y,
+                    x: Coord::at(obj_x + 1),
+                    y: Coord::at(obj_y),
                     dir: Direction::None,
-                    activation_time: Instant::now(),
+                    activation_time: 0,
                     previous_dir: Direction::None,
+                    owner: 0,
                 });
             }
-            return false;
+            false
         },
         Direction::Left => {
-            if obj_Y == 0 && obj.y < 0.1 {
+            if obj_y == 0 && obj.y.is_aligned() {
                 return true;
             }
-            if obj_Y > 0 && 
-            (walls[obj_X][obj_Y - 1].type_object == ImmovableType::Bag
-            || walls[obj_X][obj_Y - 1].type_object == ImmovableType::Emerald
-            || walls[obj_X][obj_Y - 1].type_object == ImmovableType::Wall)
-            && obj.y % (BLOCK_SIZE as f64) < 1.0 {
-                return true;
-            }
-            return false;
+            obj_y > 0 &&
+            (walls[obj_x][obj_y - 1].type_object == ImmovableType::Bag
+            || walls[obj_x][obj_y - 1].type_object == ImmovableType::Emerald
+            || walls[obj_x][obj_y - 1].type_object == ImmovableType::Wall)
+            && obj.y.step < speed
         },
         Direction::Right => {
-            if obj_Y >= walls[0].len() - 1 {
+            if obj_y >= walls[0].len() - 1 {
                 return true;
             }
-            if obj_Y < walls[0].len() - 2 && walls[obj_X][obj_Y + 1].type_object == ImmovableType::Background {
+            if obj_y < walls[0].len() - 2 && walls[obj_x][obj_y + 1].type_object == ImmovableType::Background {
                 return false;
             }
-            if obj_Y < walls[0].len() - 2 {
+            if obj_y < walls[0].len() - 2 {
                 return overlaps(obj, Movable{
                     type_object: MovableType::Converted,
-                    x: walls[obj_X][obj_Y + 1].x,
-                    y: walls[obj_X][obj_Y + 1].y,
+                    x: Coord::at(obj_x),
+                    y: Coord::at(obj_y + 1),
                     dir: Direction::None,
-                    activation_time: Instant::now(),
+                    activation_time: 0,
                     previous_dir: Direction::None,
+                    owner: 0,
                 });
             }
-            return false;
+            false
         },
-        _ => {}
+        _ => false,
     }
-    false
-}
\ No newline at end of file
+}
@@ -0,0 +1,58 @@
+use crate::clock::*;
+use crate::world::*;
+
+/// A goal that has to be met to finish a level. A level is won once all of its objectives are met.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Objective {
+    CollectEmeralds,
+    KillMonsters(usize),
+    /// Kill every monster a limited spawn schedule sends.
+    KillAllMonsters,
+    /// Stay alive for this many seconds.
+    Survive(u64),
+    ReachExit,
+}
+
+impl Objective {
+    /// Parses a comma separated list such as `emeralds, kills 5`.
+    pub fn parse_list(input: &str) -> Result<Vec<Objective>, String> {
+        input.split(',').map(|item| Objective::parse(item.trim())).collect()
+    }
+
+    fn parse(input: &str) -> Result<Objective, String> {
+        let mut words = input.split_whitespace();
+        let objective = match (words.next(), words.next()) {
+            (Some("emeralds"), None) => Some(Objective::CollectEmeralds),
+            (Some("kills"), Some(count)) => count.parse().ok().map(Objective::KillMonsters),
+            (Some("survive"), Some(seconds)) => seconds.parse().ok().map(Objective::Survive),
+            (Some("exit"), None) => Some(Objective::ReachExit),
+            (Some("monsters"), None) => Some(Objective::KillAllMonsters),
+            _ => None,
+        };
+        match objective {
+            Some(objective) if words.next().is_none() => Ok(objective),
+            _ => Err(format!("`{}` is not an objective (emeralds, kills N, monsters, survive SECONDS or exit)", input)),
+        }
+    }
+
+    pub fn is_met(&self, world: &World) -> bool {
+        match *self {
+            Objective::CollectEmeralds => world.emeralds_left() == 0,
+            Objective::KillMonsters(count) => world.kills >= count,
+            Objective::KillAllMonsters => world.all_monsters_killed(),
+            Objective::Survive(seconds) => world.tick >= seconds * TICKS_PER_SECOND,
+            Objective::ReachExit => world.living_players().into_iter().any(|player| world.exit == Some(world.player_cell(player))),
+        }
+    }
+
+    /// Short description with the progress made so far, for the HUD.
+    pub fn status(&self, world: &World) -> String {
+        match *self {
+            Objective::CollectEmeralds => format!("Emeralds {}", world.emeralds_left()),
+            Objective::KillMonsters(count) => format!("Kills {}/{}", world.kills.min(count), count),
+            Objective::KillAllMonsters => format!("Monsters {}/{}", world.kills.min(world.scheduled_monsters()), world.scheduled_monsters()),
+            Objective::Survive(seconds) => format!("Survive {}s", (seconds * TICKS_PER_SECOND).saturating_sub(world.tick) / TICKS_PER_SECOND),
+            Objective::ReachExit => "Reach the exit".to_string(),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use crate::world::*;
+use std::fmt;
+use std::fs;
+use std::io;
+
+const REPLAY_MAGIC: &str = "digger-replay";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Malformed(usize),
+    VersionMismatch(String),
+    MapMismatch,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "cannot access replay file: {}", e),
+            ReplayError::Malformed(line) => write!(f, "malformed replay file at line {}", line),
+            ReplayError::VersionMismatch(version) => write!(f, "replay was recorded with version {}, this is {}", version, VERSION),
+            ReplayError::MapMismatch => write!(f, "replay was recorded on a different map"),
+        }
+    }
+}
+
+/// A recorded session: enough to reproduce it tick for tick on the same map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replay {
+    pub version: String,
+    pub map_hash: u64,
+    pub seed: u64,
+    /// Tick, player index and command, in tick order.
+    pub inputs: Vec<(u64, usize, Command)>,
+    cursor: usize,
+}
+
+/// FNV-1a, so the hash stays the same across builds and platforms.
+pub fn map_hash(contents: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in contents.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn command_name(command: Command) -> &'static str {
+    match command {
+        Command::Up => "up",
+        Command::Down => "down",
+        Command::Left => "left",
+        Command::Right => "right",
+        Command::Fire => "fire",
+        Command::Stop => "stop",
+    }
+}
+
+fn parse_command(input: &str) -> Option<Command> {
+    match input {
+        "up" => Some(Command::Up),
+        "down" => Some(Command::Down),
+        "left" => Some(Command::Left),
+        "right" => Some(Command::Right),
+        "fire" => Some(Command::Fire),
+        "stop" => Some(Command::Stop),
+        _ => None,
+    }
+}
+
+impl Replay {
+    pub fn new(map_hash: u64, seed: u64) -> Self {
+        Replay {
+            version: VERSION.to_string(),
+            map_hash,
+            seed,
+            inputs: Vec::<(u64, usize, Command)>::new(),
+            cursor: 0,
+        }
+    }
+
+    /// Records `commands[i]` as what player `i` did at `tick`.
+    pub fn record(&mut self, tick: u64, commands: &[Vec<Command>]) {
+        for (player, commands) in commands.iter().enumerate() {
+            for command in commands {
+                self.inputs.push((tick, player, *command));
+            }
+        }
+    }
+
+    /// Hands out the commands recorded for `tick`, one list per player; ticks must be asked for in order.
+    pub fn commands_at(&mut self, tick: u64) -> Vec<Vec<Command>> {
+        let mut output = Vec::<Vec<Command>>::new();
+        while self.cursor < self.inputs.len() && self.inputs[self.cursor].0 <= tick {
+            let (at, player, command) = self.inputs[self.cursor];
+            if at == tick {
+                if output.len() <= player {
+                    output.resize(player + 1, Vec::new());
+                }
+                output[player].push(command);
+            }
+            self.cursor += 1;
+        }
+        output
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut output = format!("{} {}\nmap {:016x}\nseed {}\n", REPLAY_MAGIC, self.version, self.map_hash, self.seed);
+        // The first player's lines leave out the player number, as replays did before co-op.
+        for (tick, player, command) in &self.inputs {
+            match player {
+                0 => output.push_str(&format!("{} {}\n", tick, command_name(*command))),
+                _ => output.push_str(&format!("{} {} {}\n", tick, player + 1, command_name(*command))),
+            }
+        }
+        output
+    }
+
+    pub fn parse(input: &str) -> Result<Self, ReplayError> {
+        let mut lines = input.lines().enumerate().map(|(i, line)| (i + 1, line.split_whitespace().collect::<Vec<&str>>()));
+
+        let version = match lines.next() {
+            Some((_, ref words)) if words.len() == 2 && words[0] == REPLAY_MAGIC => words[1].to_string(),
+            _ => return Err(ReplayError::Malformed(1)),
+        };
+        if version != VERSION {
+            return Err(ReplayError::VersionMismatch(version));
+        }
+        let map_hash = match lines.next() {
+            Some((_, ref words)) if words.len() == 2 && words[0] == "map" => {
+                u64::from_str_radix(words[1], 16).map_err(|_| ReplayError::Malformed(2))?
+            },
+            _ => return Err(ReplayError::Malformed(2)),
+        };
+        let seed = match lines.next() {
+            Some((_, ref words)) if words.len() == 2 && words[0] == "seed" => {
+                words[1].parse::<u64>().map_err(|_| ReplayError::Malformed(3))?
+            },
+            _ => return Err(ReplayError::Malformed(3)),
+        };
+
+        let mut replay = Replay::new(map_hash, seed);
+        for (number, words) in lines {
+            if words.is_empty() {
+                continue;
+            }
+            let player = match words.len() {
+                2 => 0,
+                3 => match words[1].parse::<usize>() {
+                    Ok(player) if player > 0 => player - 1,
+                    _ => return Err(ReplayError::Malformed(number)),
+                },
+                _ => return Err(ReplayError::Malformed(number)),
+            };
+            let tick = words[0].parse::<u64>().map_err(|_| ReplayError::Malformed(number))?;
+            let command = parse_command(words[words.len() - 1]).ok_or(ReplayError::Malformed(number))?;
+            if replay.inputs.last().is_some_and(|&(last, _, _)| last > tick) {
+                return Err(ReplayError::Malformed(number));
+            }
+            replay.inputs.push((tick, player, command));
+        }
+        Ok(replay)
+    }
+
+    pub fn load(path: &str, map_contents: &str) -> Result<Self, ReplayError> {
+        let text = fs::read_to_string(path).map_err(ReplayError::Io)?;
+        let replay = Replay::parse(&text)?;
+        if replay.map_hash != map_hash(map_contents) {
+            return Err(ReplayError::MapMismatch);
+        }
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), ReplayError> {
+        fs::write(path, self.to_text()).map_err(ReplayError::Io)
+    }
+}
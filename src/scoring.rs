@@ -0,0 +1,108 @@
+use crate::clock::*;
+
+/// Something the player did that is worth points.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScoreEvent {
+    Emerald,
+    Gold,
+    MonsterShot,
+    MonsterCrushed,
+    /// Eaten by the player during bonus mode.
+    MonsterEaten,
+}
+
+/// Point values and bonuses, configurable per level.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScoringRules {
+    pub emerald: usize,
+    pub gold: usize,
+    pub monster_shot: usize,
+    pub monster_crushed: usize,
+    /// Points for the first monster eaten in bonus mode, doubled for each further one.
+    pub monster_eaten: usize,
+    /// Bonus for every `streak_length` emeralds collected in a row.
+    pub streak_bonus: usize,
+    pub streak_length: usize,
+    /// Longest gap between two emeralds of the same streak.
+    pub streak_ticks: u64,
+    /// An extra life is awarded each time the score passes a multiple of this, 0 for never.
+    pub extra_life_every: usize,
+}
+
+impl Default for ScoringRules {
+    fn default() -> Self {
+        ScoringRules {
+            emerald: 20,
+            gold: 500,
+            monster_shot: 250,
+            monster_crushed: 500,
+            monster_eaten: 200,
+            streak_bonus: 250,
+            streak_length: 8,
+            streak_ticks: TICKS_PER_SECOND,
+            extra_life_every: 20000,
+        }
+    }
+}
+
+/// Turns score events into points, keeping track of the running emerald streak.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Scoring {
+    pub rules: ScoringRules,
+    streak: usize,
+    last_emerald: u64,
+    eaten: u32,
+}
+
+impl Scoring {
+    pub fn new(rules: ScoringRules) -> Self {
+        Scoring {
+            rules,
+            streak: 0,
+            last_emerald: 0,
+            eaten: 0,
+        }
+    }
+
+    /// Points `event` is worth when it happens at `tick`.
+    pub fn points(&mut self, event: ScoreEvent, tick: u64) -> usize {
+        match event {
+            ScoreEvent::Emerald => {
+                if self.streak > 0 && tick - self.last_emerald > self.rules.streak_ticks {
+                    self.streak = 0;
+                }
+                self.streak += 1;
+                self.last_emerald = tick;
+                if self.rules.streak_length > 0 && self.streak.is_multiple_of(self.rules.streak_length) {
+                    self.rules.emerald + self.rules.streak_bonus
+                } else {
+                    self.rules.emerald
+                }
+            },
+            ScoreEvent::Gold => self.rules.gold,
+            ScoreEvent::MonsterShot => self.rules.monster_shot,
+            ScoreEvent::MonsterCrushed => self.rules.monster_crushed,
+            ScoreEvent::MonsterEaten => {
+                self.eaten += 1;
+                self.rules.monster_eaten << (self.eaten - 1).min(16)
+            },
+        }
+    }
+
+    /// Starts counting eaten monsters from the first again.
+    pub fn start_bonus(&mut self) {
+        self.eaten = 0;
+    }
+
+    pub fn break_streak(&mut self) {
+        self.streak = 0;
+    }
+
+    /// Extra lives earned by the score going from `before` to `after`.
+    pub fn extra_lives(&self, before: usize, after: usize) -> usize {
+        if self.rules.extra_life_every == 0 {
+            return 0;
+        }
+        after / self.rules.extra_life_every - before / self.rules.extra_life_every
+    }
+}
@@ -0,0 +1,102 @@
+use crate::campaign::*;
+use crate::level::*;
+use crate::world::*;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GameState {
+    Title,
+    Playing,
+    Paused,
+    LevelComplete,
+    GameOver,
+    /// The last level of the campaign was completed.
+    Victory,
+}
+
+/// Everything between the window and the rules: which level is loaded and what screen is up.
+pub struct Session {
+    pub campaign: Campaign,
+    pub world: World,
+    pub state: GameState,
+    /// Progress the current level was entered with, used when restarting it.
+    entry: Option<Vec<Progress>>,
+    seed: u64,
+}
+
+impl Session {
+    pub fn new(campaign: Campaign, seed: u64) -> Result<Self, MapError> {
+        let world = campaign.load_level(None, seed)?;
+        Ok(Session {
+            campaign,
+            world,
+            state: GameState::Title,
+            entry: None,
+            seed,
+        })
+    }
+
+    /// Runs one tick of the level with each player's commands; does nothing unless a level is being played.
+    pub fn step(&mut self, commands: &[Vec<Command>]) {
+        if self.state != GameState::Playing {
+            return;
+        }
+        match self.world.step_players(commands) {
+            Ok(()) => {},
+            Err(RuntimeError::Victory) if self.campaign.is_last() => self.state = GameState::Victory,
+            Err(RuntimeError::Victory) => self.state = GameState::LevelComplete,
+            Err(RuntimeError::PlayerDead) | Err(RuntimeError::OutOfTime) => self.state = GameState::GameOver,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.state = match self.state {
+            GameState::Playing => GameState::Paused,
+            GameState::Paused => GameState::Playing,
+            state => state,
+        };
+    }
+
+    /// The confirm action of every screen. Returns true when a new world was loaded.
+    pub fn confirm(&mut self) -> Result<bool, MapError> {
+        match self.state {
+            GameState::Title => {
+                self.state = GameState::Playing;
+                Ok(false)
+            },
+            GameState::LevelComplete => {
+                let progress = self.world.progress();
+                self.campaign.advance();
+                self.enter_level(Some(progress))?;
+                Ok(true)
+            },
+            GameState::GameOver => {
+                self.enter_level(self.entry.clone())?;
+                Ok(true)
+            },
+            GameState::Victory => {
+                self.campaign.restart();
+                self.enter_level(None)?;
+                Ok(true)
+            },
+            GameState::Playing | GameState::Paused => Ok(false),
+        }
+    }
+
+    /// Starts the level being played over with the progress it was entered with. Returns true if it did.
+    pub fn restart(&mut self) -> Result<bool, MapError> {
+        match self.state {
+            GameState::Playing | GameState::Paused | GameState::GameOver => {
+                self.enter_level(self.entry.clone())?;
+                Ok(true)
+            },
+            _ => Ok(false),
+        }
+    }
+
+    fn enter_level(&mut self, progress: Option<Vec<Progress>>) -> Result<(), MapError> {
+        self.world = self.campaign.load_level(progress.as_deref(), self.seed)?;
+        self.entry = progress;
+        self.state = GameState::Playing;
+        Ok(())
+    }
+}
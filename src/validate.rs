@@ -0,0 +1,130 @@
+use std::collections::VecDeque;
+use std::fmt;
+use crate::level::*;
+use crate::objective::*;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A structural problem with a level. `row` and `column` are 1-based grid positions, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub severity: Severity,
+    pub row: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        if self.row == 0 {
+            write!(f, "{}: {}", severity, self.message)
+        } else {
+            write!(f, "{}: row {}, column {}: {}", severity, self.row, self.column, self.message)
+        }
+    }
+}
+
+fn problem(severity: Severity, cell: Option<(usize, usize)>, message: &str) -> Problem {
+    let (row, column) = cell.map_or((0, 0), |(x, y)| (x + 1, y + 1));
+    Problem {
+        severity,
+        row,
+        column,
+        message: message.to_string(),
+    }
+}
+
+fn neighbours(x: usize, y: usize, rows: usize, columns: usize) -> Vec<(usize, usize)> {
+    let mut output = Vec::<(usize, usize)>::new();
+    if x > 0 {
+        output.push((x - 1, y));
+    }
+    if x < rows - 1 {
+        output.push((x + 1, y));
+    }
+    if y > 0 {
+        output.push((x, y - 1));
+    }
+    if y < columns - 1 {
+        output.push((x, y + 1));
+    }
+    output
+}
+
+/// Cells the player can dig to from its starting cell. Bags count as blocking, even though they can be pushed sideways.
+fn reachable(level: &Level) -> Vec<Vec<bool>> {
+    let rows = level.cells.len();
+    let columns = level.cells[0].len();
+    let mut visited = vec![vec![false; columns]; rows];
+    let mut queue = VecDeque::<(usize, usize)>::new();
+    for (x, row) in level.cells.iter().enumerate() {
+        for (y, cell) in row.iter().enumerate() {
+            if *cell == Cell::Player {
+                visited[x][y] = true;
+                queue.push_back((x, y));
+            }
+        }
+    }
+
+    while let Some((x, y)) = queue.pop_front() {
+        for (nx, ny) in neighbours(x, y, rows, columns) {
+            if !visited[nx][ny] && level.cells[nx][ny] != Cell::Gold {
+                visited[nx][ny] = true;
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+    visited
+}
+
+/// Checks a parsed level for problems that make it unwinnable or behave oddly.
+pub fn validate(level: &Level) -> Vec<Problem> {
+    let mut problems = Vec::<Problem>::new();
+    let rows = level.cells.len();
+    let columns = level.cells[0].len();
+    let reached = reachable(level);
+    let emeralds = level.objectives.contains(&Objective::CollectEmeralds);
+
+    if emeralds && !level.cells.iter().flatten().any(|&cell| cell == Cell::Diamond) {
+        problems.push(problem(Severity::Error, None, "level has no emeralds, so it is won as soon as it starts"));
+    }
+    if level.objectives.contains(&Objective::KillAllMonsters) && level.spawn.total == 0 {
+        problems.push(problem(Severity::Error, None, "level has the monsters objective but no total_monsters limit"));
+    }
+    if level.objectives.contains(&Objective::ReachExit) && !level.cells.iter().flatten().any(|&cell| cell == Cell::Exit) {
+        problems.push(problem(Severity::Error, None, "level has the exit objective but no exit cell"));
+    }
+
+    for (x, row) in level.cells.iter().enumerate() {
+        for (y, cell) in row.iter().enumerate() {
+            let around = neighbours(x, y, rows, columns);
+            match cell {
+                Cell::Diamond if emeralds && !reached[x][y] => {
+                    problems.push(problem(Severity::Error, Some((x, y)), "emerald cannot be reached by the player"));
+                },
+                Cell::Exit if !reached[x][y] => {
+                    problems.push(problem(Severity::Error, Some((x, y)), "exit cannot be reached by the player"));
+                },
+                Cell::Player if around.iter().all(|&(nx, ny)| level.cells[nx][ny] == Cell::Gold) => {
+                    problems.push(problem(Severity::Error, Some((x, y)), "player is enclosed and cannot dig anywhere"));
+                },
+                Cell::Spawn if around.iter().all(|&(nx, ny)| matches!(level.cells[nx][ny], Cell::Wall | Cell::Gold | Cell::Diamond)) => {
+                    problems.push(problem(Severity::Error, Some((x, y)), "spawn point is walled in, monsters cannot leave it"));
+                },
+                Cell::Gold if x == rows - 1 || !reached[x + 1][y] => {
+                    problems.push(problem(Severity::Warning, Some((x, y)), "bag can never fall, the player cannot get underneath it"));
+                },
+                _ => {},
+            }
+        }
+    }
+    problems
+}
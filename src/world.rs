@@ -0,0 +1,1039 @@
+use std::collections::VecDeque;
+use crate::clock::TICKS_PER_SECOND;
+use crate::immovable_objects::*;
+use crate::movable_objects::*;
+use crate::level::*;
+use crate::objective::*;
+use crate::scoring::*;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuntimeError {
+    PlayerDead,
+    Victory,
+    OutOfTime,
+}
+
+/// Player commands fed into `World::step`, independent of any input device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Command {
+    Up,
+    Down,
+    Left,
+    Right,
+    Fire,
+    Stop,
+}
+
+pub const BLOCK_SIZE: usize = 30;
+/// Positions are whole cells plus a whole number of these steps, so movement and collisions are exact.
+pub const STEPS_PER_CELL: u32 = 600;
+/// Speeds in steps per tick. The player's speed is set per level.
+pub const MONSTER_SPEED: u32 = 10;
+pub const HOBBIN_SPEED: u32 = 5;
+pub const BAG_SPEED: u32 = 10;
+pub const SHOT_SPEED: u32 = 16;
+pub const DEATH_TICKS: u64 = 2 * TICKS_PER_SECOND;
+pub const INVULNERABLE_TICKS: u64 = 3 * TICKS_PER_SECOND;
+/// How long a bag wobbles once its support is dug out before it starts to fall.
+pub const TIME_TO_FALL: u64 = 3 * TICKS_PER_SECOND;
+/// A bag that falls at least this many rows breaks when it lands.
+pub const ROWS_TO_BREAK: usize = 2;
+pub const TIME_TO_SPAWN: u64 = 3 * TICKS_PER_SECOND;
+const DEFAULT_MAX_MONSTERS: usize = 3;
+
+/// What a player keeps when moving on to the next level of a campaign.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Progress {
+    pub score: usize,
+    pub shots: usize,
+    pub lives: usize,
+}
+
+/// One player's own score, weapon, lives and controls. Player `i` is the `MovableType::Player` owned by `i`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PlayerState {
+    pub score: usize,
+    pub shots: usize,
+    pub lives: usize,
+    /// Emerald streak and bonus kills, counted apart for each player.
+    pub scoring: Scoring,
+    /// Tick at which the player comes back after dying, 0 while playing.
+    pub dying_until: u64,
+    pub invulnerable_until: u64,
+    /// Cell the player starts from.
+    start: (usize, usize),
+    /// Direction currently held down, the player keeps going while it is.
+    held: Direction,
+    /// Last direction asked for, taken at the next cell boundary even if it is no longer held.
+    queued: Direction,
+}
+
+impl PlayerState {
+    /// A player without lives sits out the rest of the level.
+    pub fn is_out(&self) -> bool {
+        self.lives == 0
+    }
+
+    /// Shown dead and frozen in place until `dying_until`.
+    pub fn is_dying(&self) -> bool {
+        self.dying_until > 0
+    }
+
+    pub fn progress(&self) -> Progress {
+        Progress {
+            score: self.score,
+            shots: self.shots,
+            lives: self.lives,
+        }
+    }
+}
+
+/// Which spawn point the next monster comes out of.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SpawnOrder {
+    RoundRobin,
+    /// Picked with the world's seeded random numbers, so replays stay deterministic.
+    Random,
+}
+
+/// How monsters enter a level, configurable per level.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SpawnSchedule {
+    /// Monsters the level sends in total, 0 for no limit. The `monsters` objective asks for all of them to be killed.
+    pub total: usize,
+    /// Most monsters alive at the same time.
+    pub max_concurrent: usize,
+    /// Ticks between two spawns.
+    pub interval: u64,
+    pub order: SpawnOrder,
+}
+
+impl Default for SpawnSchedule {
+    fn default() -> Self {
+        SpawnSchedule {
+            total: 0,
+            max_concurrent: DEFAULT_MAX_MONSTERS,
+            interval: TIME_TO_SPAWN,
+            order: SpawnOrder::RoundRobin,
+        }
+    }
+}
+
+/// What broken bags do, configurable per level.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GoldRules {
+    /// Picking up gold also gives the player a shot.
+    pub recharges_weapon: bool,
+    /// Monsters destroy gold they touch instead of passing over it.
+    pub monsters_eat: bool,
+}
+
+impl Default for GoldRules {
+    fn default() -> Self {
+        GoldRules {
+            recharges_weapon: true,
+            monsters_eat: false,
+        }
+    }
+}
+
+/// The whole game state and rules, without any window or texture attached.
+pub struct World {
+    pub name: String,
+    pub immovable: Vec<Vec<Immovable>>,
+    pub movable: Vec<Movable>,
+    pub line_len: usize,
+    pub num_lines: usize,
+    /// Row and column of every spawn point, in map order.
+    spawns: Vec<(usize, usize)>,
+    /// Everyone playing, in the order their cells appear on the map.
+    pub players: Vec<PlayerState>,
+    /// Steps per tick the players move.
+    player_speed: u32,
+    previous: Vec<i32>,
+    pub tick: u64,
+    /// Ticks allowed for the level, 0 for no limit.
+    pub time_limit: u64,
+    pub seed: u64,
+    last_spawned: u64,
+    current_number_monsters: usize,
+    schedule: SpawnSchedule,
+    /// Spawn point used last by `SpawnOrder::RoundRobin`.
+    next_spawn: usize,
+    /// State of the random number generator, derived from `seed` on first use.
+    rng: u64,
+    pub gold: GoldRules,
+    pub objectives: Vec<Objective>,
+    /// Monsters killed on this level.
+    pub kills: usize,
+    /// Cell the `ReachExit` objective asks for, if the map has one.
+    pub exit: Option<(usize, usize)>,
+    /// Kinds of monster to spawn, cycled through in order.
+    monster_pattern: Vec<MovableType>,
+    monsters_spawned: usize,
+    /// Ticks after which a Nobbin turns into a Hobbin, 0 for never.
+    hobbin_after: u64,
+    /// Shortest paths for Hobbins, which may dig through walls.
+    digging_previous: Vec<i32>,
+    /// Kills after which the bonus appears on the spawn point, 0 for never.
+    bonus_after: usize,
+    bonus_shown: bool,
+    /// How long bonus mode lasts once the bonus is eaten.
+    bonus_ticks: u64,
+    /// Tick at which bonus mode ends, 0 when it is not active.
+    pub bonus_until: u64,
+}
+
+impl Default for World {
+    fn default() -> Self {
+        World::new()
+    }
+}
+
+impl World {
+    pub fn new() -> Self {
+        World {
+            name: String::new(),
+            immovable: Vec::<Vec<Immovable>>::new(),
+            movable: Vec::<Movable>::new(),
+            line_len: 0,
+            num_lines: 0,
+            spawns: Vec::<(usize, usize)>::new(),
+            players: Vec::<PlayerState>::new(),
+            player_speed: STEPS_PER_CELL,
+            previous: Vec::<i32>::new(),
+            tick: 0,
+            time_limit: 0,
+            seed: 0,
+            last_spawned: 0,
+            current_number_monsters: 0,
+            schedule: SpawnSchedule::default(),
+            next_spawn: 0,
+            rng: 0,
+            gold: GoldRules::default(),
+            objectives: vec![Objective::CollectEmeralds],
+            kills: 0,
+            exit: None,
+            monster_pattern: vec![MovableType::Monster],
+            monsters_spawned: 0,
+            hobbin_after: 0,
+            digging_previous: Vec::<i32>::new(),
+            bonus_after: 0,
+            bonus_shown: false,
+            bonus_ticks: 0,
+            bonus_until: 0,
+        }
+    }
+
+    /// Builds the world for `level`; `seed` drives every random choice, the first spawn included.
+    pub fn from_level(level: &Level, seed: u64) -> Self {
+        let mut world = World::new();
+        world.seed = seed;
+        world.name = level.name.clone();
+        world.schedule = level.spawn;
+        world.time_limit = level.time_limit * TICKS_PER_SECOND;
+        world.gold = level.gold;
+        world.objectives = level.objectives.clone();
+        world.monster_pattern = level.monsters.clone();
+        world.hobbin_after = level.hobbin_after * TICKS_PER_SECOND;
+        world.bonus_after = level.bonus_after;
+        world.bonus_ticks = level.bonus_time * TICKS_PER_SECOND;
+        world.player_speed = (level.player_speed * STEPS_PER_CELL as u64 / TICKS_PER_SECOND).min(STEPS_PER_CELL as u64) as u32;
+        for row in &level.cells {
+            world.add_row(row);
+            world.num_lines += 1;
+        }
+        for player in world.players.iter_mut() {
+            player.lives = level.lives;
+            player.scoring = Scoring::new(level.scoring);
+        }
+        world.line_len = level.cells.first().map_or(0, |row| row.len());
+        world.previous.resize(world.line_len * world.num_lines, -1);
+        world.digging_previous.resize(world.line_len * world.num_lines, -1);
+        world.spawn_monster();
+        world
+    }
+
+    fn add_row(&mut self, input: &[Cell]) {
+
+        let current_x = self.immovable.len();
+        let mut new_immovable_line = Vec::<Immovable>::new();
+
+        for (i, cell) in input.iter().enumerate() {
+            let type_object = match cell {
+                Cell::Space | Cell::Player | Cell::Spawn | Cell::Exit => ImmovableType::Background,
+                Cell::Wall => ImmovableType::Wall,
+                Cell::Gold => ImmovableType::Bag,
+                Cell::Diamond => ImmovableType::Emerald,
+            };
+            new_immovable_line.push(Immovable{
+                type_object,
+            });
+            match cell {
+                Cell::Player => {
+                    self.movable.push(Movable{
+                        type_object: MovableType::Player,
+                        x: Coord::at(current_x),
+                        y: Coord::at(i),
+                        dir: Direction::None,
+                        activation_time: self.tick,
+                        previous_dir: Direction::Right,
+                        owner: self.players.len(),
+                    });
+                    self.players.push(PlayerState {
+                        score: 0,
+                        shots: 0,
+                        lives: 0,
+                        scoring: Scoring::new(ScoringRules::default()),
+                        dying_until: 0,
+                        invulnerable_until: 0,
+                        start: (current_x, i),
+                        held: Direction::None,
+                        queued: Direction::None,
+                    });
+                },
+                Cell::Exit => self.exit = Some((current_x, i)),
+                Cell::Spawn => self.spawns.push((current_x, i)),
+                _ => {},
+            }
+        }
+
+        self.immovable.push(new_immovable_line);
+    }
+
+    /// What each player takes on to the next level.
+    pub fn progress(&self) -> Vec<Progress> {
+        self.players.iter().map(PlayerState::progress).collect()
+    }
+
+    pub fn emeralds_left(&self) -> usize {
+        self.immovable.iter().flatten().filter(|cell| cell.type_object == ImmovableType::Emerald).count()
+    }
+
+    /// Starts this level from where the previous one left off, player by player.
+    pub fn carry(&mut self, progress: &[Progress]) {
+        for (player, progress) in self.players.iter_mut().zip(progress) {
+            player.score = progress.score;
+            player.shots = progress.shots;
+            player.lives = progress.lives;
+        }
+    }
+
+    /// Advances the simulation by one tick with `commands` for the first player.
+    #[cfg(test)]
+    pub fn step(&mut self, commands: &[Command]) -> Result<(), RuntimeError> {
+        self.step_players(&[commands.to_vec()])
+    }
+
+    /// Advances the simulation by exactly one tick, applying `commands[i]` for player `i` first.
+    pub fn step_players(&mut self, commands: &[Vec<Command>]) -> Result<(), RuntimeError> {
+        self.tick += 1;
+        if self.time_limit > 0 && self.tick > self.time_limit {
+            return Err(RuntimeError::OutOfTime);
+        }
+        let held = self.is_dying();
+        for player in 0..self.players.len() {
+            if self.players[player].is_dying() && self.tick >= self.players[player].dying_until {
+                self.respawn(player);
+            }
+        }
+        if self.is_dying() {
+            return Ok(());
+        }
+        if held {
+            self.reset_monsters();
+        }
+        for (player, commands) in commands.iter().enumerate().take(self.players.len()) {
+            if !self.is_active(player) {
+                continue;
+            }
+            for command in commands {
+                self.apply_command(player, *command);
+            }
+        }
+        for player in self.active_players() {
+            self.move_player(player);
+        }
+        self.movable = move_object(self.movable.clone());
+        self.check_for_colision()?;
+        if self.is_dying() {
+            return Ok(());
+        }
+        self.update_game()
+    }
+
+    /// The round holds while every player still in the game is dying.
+    pub fn is_dying(&self) -> bool {
+        self.players.iter().any(PlayerState::is_dying)
+        && self.players.iter().all(|player| player.is_dying() || player.is_out())
+    }
+
+    pub fn is_invulnerable(&self, player: usize) -> bool {
+        self.tick < self.players[player].invulnerable_until
+    }
+
+    /// Adds the points for `event` to `player`, handing out any extra life they earn.
+    fn score(&mut self, player: usize, event: ScoreEvent) {
+        let tick = self.tick;
+        let player = &mut self.players[player];
+        let before = player.score;
+        player.score += player.scoring.points(event, tick);
+        player.lives += player.scoring.extra_lives(before, player.score);
+    }
+
+    pub fn is_bonus_active(&self) -> bool {
+        self.tick < self.bonus_until
+    }
+
+    /// Ticks of bonus mode left.
+    pub fn bonus_left(&self) -> u64 {
+        self.bonus_until.saturating_sub(self.tick)
+    }
+
+    /// Takes a life from `player` and holds them for a moment; the game is over once no player has any left.
+    fn lose_life(&mut self, player: usize) -> Result<(), RuntimeError> {
+        self.bonus_until = 0;
+        let state = &mut self.players[player];
+        state.scoring.break_streak();
+        state.lives = state.lives.saturating_sub(1);
+        state.dying_until = self.tick + DEATH_TICKS;
+        if self.living_players().is_empty() {
+            return Err(RuntimeError::PlayerDead);
+        }
+        let player_position = self.player_pos(player);
+        self.movable[player_position].dir = Direction::None;
+        Ok(())
+    }
+
+    /// Puts `player` back on their starting cell once they are done dying; a player without lives just stays out.
+    fn respawn(&mut self, player: usize) {
+        let player_position = self.player_pos(player);
+        let state = &mut self.players[player];
+        state.dying_until = 0;
+        if state.is_out() {
+            return;
+        }
+        state.invulnerable_until = self.tick + INVULNERABLE_TICKS;
+        state.held = Direction::None;
+        state.queued = Direction::None;
+        let (x, y) = state.start;
+        self.movable[player_position].x = Coord::at(x);
+        self.movable[player_position].y = Coord::at(y);
+        self.movable[player_position].dir = Direction::None;
+    }
+
+    /// Puts the monsters back on the spawn points when the round starts again.
+    fn reset_monsters(&mut self) {
+        let spawns = self.spawns.clone();
+        for (i, object) in self.movable.iter_mut().filter(|object| object.type_object.is_monster()).enumerate() {
+            let (x, y) = spawns[i % spawns.len()];
+            object.x = Coord::at(x);
+            object.y = Coord::at(y);
+            object.dir = Direction::None;
+        }
+        self.last_spawned = self.tick;
+    }
+
+    fn apply_command(&mut self, player: usize, command: Command) {
+
+        let player_position = self.player_pos(player);
+        match command {
+            Command::Left => self.steer(player, Direction::Left),
+            Command::Up => self.steer(player, Direction::Up),
+            Command::Right => self.steer(player, Direction::Right),
+            Command::Down => self.steer(player, Direction::Down),
+            Command::Fire => {
+                if self.players[player].shots < 1 {
+                    return;
+                }
+                self.players[player].shots -= 1;
+                let shooter = player;
+                let player = self.movable[player_position];
+                let cell = STEPS_PER_CELL as i64;
+                let (shot_x, shot_y) = match player.previous_dir {
+                    Direction::Right => (player.x, player.y.advance(cell)),
+                    Direction::Left => (player.x, player.y.advance(-cell)),
+                    Direction::Up => (player.x.advance(-cell), player.y),
+                    Direction::Down => (player.x.advance(cell), player.y),
+                    Direction::None => return,
+                };
+                self.movable.push(Movable{
+                    type_object: MovableType::Shot,
+                    x: shot_x,
+                    y: shot_y,
+                    dir: player.previous_dir,
+                    activation_time: self.tick,
+                    previous_dir: player.previous_dir,
+                    owner: shooter,
+                });
+            }
+            Command::Stop => self.players[player].held = Direction::None,
+        }
+    }
+
+    /// Holds `dir` down for `player`. Turning back is possible at any time, other turns wait for the next cell boundary.
+    fn steer(&mut self, player: usize, dir: Direction) {
+        self.players[player].held = dir;
+        self.players[player].queued = dir;
+        let player_position = self.player_pos(player);
+        let player = &mut self.movable[player_position];
+        if player.dir != Direction::None && dir == player.dir.reverse() {
+            player.dir = dir;
+            player.previous_dir = dir;
+        }
+    }
+
+    /// Whether `player`, standing exactly on a cell, can move on in direction `dir`. Pushes a bag in the way if it can.
+    /// A wobbling bag blocks the way like a resting one, but cannot be pushed.
+    fn can_enter(&mut self, player: usize, dir: Direction) -> bool {
+        let (x, y) = self.player_cell(player);
+        if self.neighbour(x, y, dir).is_some_and(|(nx, ny)| self.is_wobbling_bag(nx, ny)) {
+            return false;
+        }
+        match dir {
+            Direction::Left => y > 0 && (self.immovable[x][y - 1].type_object != ImmovableType::Bag || (y > 1 && self.push_bag(x, y - 1, y - 2))),
+            Direction::Up => x > 0 && self.immovable[x - 1][y].type_object != ImmovableType::Bag,
+            Direction::Right => y < self.line_len - 1
+                && (self.immovable[x][y + 1].type_object != ImmovableType::Bag || (y + 2 < self.line_len && self.push_bag(x, y + 1, y + 2))),
+            Direction::Down => x < self.num_lines - 1 && self.immovable[x + 1][y].type_object != ImmovableType::Bag,
+            Direction::None => false,
+        }
+    }
+
+    /// Picks the player's direction on a cell boundary: the queued turn, else the held direction, else stop.
+    fn choose_direction(&mut self, player: usize) {
+        let queued = std::mem::replace(&mut self.players[player].queued, Direction::None);
+        let held = self.players[player].held;
+        let dir = if queued != Direction::None && self.can_enter(player, queued) {
+            queued
+        } else if held != Direction::None && held != queued && self.can_enter(player, held) {
+            held
+        } else {
+            Direction::None
+        };
+        let player_position = self.player_pos(player);
+        let player = &mut self.movable[player_position];
+        player.dir = dir;
+        if dir != Direction::None {
+            player.previous_dir = dir;
+        }
+    }
+
+    /// Glides `player` `player_speed` steps, choosing a new direction at every cell boundary it reaches.
+    fn move_player(&mut self, player: usize) {
+        let mut left = self.player_speed;
+        let player_position = self.player_pos(player);
+        while left > 0 {
+            let current = self.movable[player_position];
+            if current.x.is_aligned() && current.y.is_aligned() {
+                self.choose_direction(player);
+            }
+            let player = &mut self.movable[player_position];
+            let (coord, forward) = match player.dir {
+                Direction::Up => (&mut player.x, false),
+                Direction::Down => (&mut player.x, true),
+                Direction::Left => (&mut player.y, false),
+                Direction::Right => (&mut player.y, true),
+                Direction::None => return,
+            };
+            let to_boundary = match (coord.step, forward) {
+                (0, _) => STEPS_PER_CELL,
+                (step, true) => STEPS_PER_CELL - step,
+                (step, false) => step,
+            };
+            let steps = left.min(to_boundary);
+            *coord = coord.advance(if forward { steps as i64 } else { -(steps as i64) });
+            left -= steps;
+        }
+    }
+
+    /// Whether a bag about to fall sits on the cell (`x`, `y`).
+    fn is_wobbling_bag(&self, x: usize, y: usize) -> bool {
+        self.movable.iter().any(|m| m.type_object == MovableType::FallingBag && m.dir == Direction::None
+            && m.x == Coord::at(x) && m.y == Coord::at(y))
+    }
+
+    /// Slides the bag in row `x` from column `from` to column `to` if that cell is an empty tunnel.
+    /// Anything already in that cell blocks the push, so bags never move in a chain or onto a monster, player or gold.
+    /// Players out of lives are no longer on the board and do not block.
+    fn push_bag(&mut self, x: usize, from: usize, to: usize) -> bool {
+        let target = Movable{
+            type_object: MovableType::FallingBag,
+            x: Coord::at(x),
+            y: Coord::at(to),
+            dir: Direction::None,
+            activation_time: 0,
+            previous_dir: Direction::None,
+            owner: 0,
+        };
+        if self.immovable[x][to].type_object != ImmovableType::Background
+        || self.movable.iter().any(|m| overlaps(*m, target)
+            && !(m.type_object == MovableType::Player && self.players[m.owner].is_out())) {
+            return false;
+        }
+        self.immovable[x][to].type_object = ImmovableType::Bag;
+        self.immovable[x][from].type_object = ImmovableType::Background;
+        true
+    }
+
+    /// Row and column of the cell `player` is in.
+    pub fn player_cell(&self, player: usize) -> (usize, usize) {
+        let player = self.movable[self.player_pos(player)];
+        (player.x.cell, player.y.cell)
+    }
+
+    /// Index in `movable` of `player`.
+    pub fn player_pos(&self, player: usize) -> usize {
+        for i in 0..self.movable.len() {
+            if self.movable[i].type_object == MovableType::Player && self.movable[i].owner == player {
+                return i;
+            };
+        }
+        0
+    }
+
+    /// Players that still have lives left.
+    pub fn living_players(&self) -> Vec<usize> {
+        (0..self.players.len()).filter(|&player| !self.players[player].is_out()).collect()
+    }
+
+    /// Whether `player` is on the board: not out and not dying.
+    fn is_active(&self, player: usize) -> bool {
+        !self.players[player].is_out() && !self.players[player].is_dying()
+    }
+
+    /// Players on the board, the ones monsters chase and bags fall for.
+    fn active_players(&self) -> Vec<usize> {
+        (0..self.players.len()).filter(|&player| self.is_active(player)).collect()
+    }
+
+    /// The player on the board closest to the cell (`x`, `y`), counted in cells along both axes.
+    fn nearest_player(&self, x: usize, y: usize) -> usize {
+        self.active_players().into_iter()
+            .min_by_key(|&player| {
+                let (player_x, player_y) = self.player_cell(player);
+                player_x.abs_diff(x) + player_y.abs_diff(y)
+            })
+            .unwrap_or(0)
+    }
+
+    /// Cells `player` covers, two while it is between cells.
+    fn player_cells(&self, player: usize) -> Vec<(usize, usize)> {
+        let player = self.movable[self.player_pos(player)];
+        let mut cells = vec![(player.x.cell, player.y.cell)];
+        if !player.x.is_aligned() {
+            cells.push((player.x.cell + 1, player.y.cell));
+        }
+        if !player.y.is_aligned() {
+            cells.push((player.x.cell, player.y.cell + 1));
+        }
+        cells
+    }
+
+    fn update_game(&mut self) -> Result<(), RuntimeError> {
+
+            for player in self.active_players() {
+                for (x, y) in self.player_cells(player) {
+                    match self.immovable[x][y].type_object {
+                        ImmovableType::Emerald => self.score(player, ScoreEvent::Emerald),
+                        ImmovableType::Bonus => {
+                            self.bonus_until = self.tick + self.bonus_ticks;
+                            for state in self.players.iter_mut() {
+                                state.scoring.start_bonus();
+                            }
+                        },
+                        _ => {},
+                    }
+                    self.immovable[x][y].type_object = ImmovableType::Background;
+                }
+            }
+            if self.objectives.iter().all(|objective| objective.is_met(self)) {
+                return Err(RuntimeError::Victory);
+            };
+
+            self.activate_bags();
+            self.drop_bags();
+            self.transform_monsters();
+            self.show_bonus();
+            self.spawn_monster();
+            self.find_shortest_paths();
+            self.redirect_monsters();
+
+            Ok(())
+    }
+
+    fn find_shortest_paths(&mut self) {
+        self.previous = self.shortest_paths(false);
+        if self.movable.iter().any(|object| object.type_object == MovableType::Hobbin) {
+            self.digging_previous = self.shortest_paths(true);
+        }
+    }
+
+    fn passable(cell: &Immovable, digging: bool) -> bool {
+        match cell.type_object {
+            ImmovableType::Background | ImmovableType::Bonus => true,
+            ImmovableType::Wall => digging,
+            _ => false,
+        }
+    }
+
+    /// Breadth-first search from every player on the board at once. For each cell, the next cell on the way
+    /// to the nearest player or -1 if there is none. Walls are passable only when `digging`.
+    fn shortest_paths(&self, digging: bool) -> Vec<i32> {
+        let passable = |cell: &Immovable| World::passable(cell, digging);
+        let mut previous = vec![-1; self.line_len * self.num_lines];
+        let mut visited = vec![false; self.line_len * self.num_lines];
+        let mut queue = VecDeque::<usize>::new();
+        for player in self.active_players() {
+            let (player_x, player_y) = self.player_cell(player);
+            let start = player_x * self.line_len + player_y;
+            visited[start] = true;
+            queue.push_back(start);
+        }
+
+        while let Some(current) = queue.pop_front() {
+            let current_x = current / self.line_len;
+            let current_y = current % self.line_len;
+
+            if current_y < self.line_len - 1
+            && passable(&self.immovable[current_x][current_y + 1])
+            && !visited[current + 1] {
+                visited[current + 1] = true;
+                queue.push_back(current + 1);
+                previous[current + 1] = current as i32;
+            };
+
+            if current_y > 0
+            && passable(&self.immovable[current_x][current_y - 1])
+            && !visited[current - 1] {
+                visited[current - 1] = true;
+                queue.push_back(current - 1);
+                previous[current - 1] = current as i32;
+            };
+
+            if current_x < self.num_lines - 1
+            && passable(&self.immovable[current_x + 1][current_y])
+            && !visited[current + self.line_len]{
+                visited[current + self.line_len] = true;
+                queue.push_back(current + self.line_len);
+                previous[current + self.line_len] = current as i32;
+            };
+
+            if current_x > 0
+            && passable(&self.immovable[current_x - 1][current_y])
+            && !visited[current - self.line_len]{
+                visited[current - self.line_len] = true;
+                queue.push_back(current - self.line_len);
+                previous[current - self.line_len] = current as i32;
+            };
+        }
+        previous
+    }
+
+    /// Sends every monster along its shortest path to the nearest player, or away from it in bonus mode.
+    fn redirect_monsters(&mut self) {
+        let fleeing = self.is_bonus_active();
+        for i in 0..self.movable.len() {
+            if self.movable[i].type_object.is_monster() {
+                let digging = self.movable[i].type_object == MovableType::Hobbin;
+                let monster_x = self.movable[i].x.cell as i32;
+                let monster_y = self.movable[i].y.cell as i32;
+
+                let paths = if digging { &self.digging_previous } else { &self.previous };
+                let previous_position = paths[(monster_x as usize) * self.line_len + (monster_y as usize)];
+                if previous_position < 0 {
+                    // Already on a player's cell, or cut off from every player: wait there rather than drift on through walls.
+                    self.movable[i].x = Coord::at(monster_x as usize);
+                    self.movable[i].y = Coord::at(monster_y as usize);
+                    self.movable[i].dir = Direction::None;
+                    continue;
+                }
+                let previous_x = previous_position / (self.line_len as i32);
+                let previous_y = previous_position % (self.line_len as i32);
+
+                let toward = if previous_x == monster_x - 1 {
+                    Direction::Up
+                } else if previous_x == monster_x + 1 {
+                    Direction::Down
+                } else if previous_y == monster_y - 1 {
+                    Direction::Left
+                } else {
+                    Direction::Right
+                };
+                let dir = if fleeing {
+                    self.flee_direction(monster_x as usize, monster_y as usize, toward, digging)
+                } else {
+                    toward
+                };
+
+                match dir {
+                    Direction::Up | Direction::Down => self.movable[i].y = Coord::at(monster_y as usize),
+                    Direction::Left | Direction::Right => self.movable[i].x = Coord::at(monster_x as usize),
+                    Direction::None => {},
+                }
+                self.movable[i].dir = dir;
+                if let Some((x, y)) = self.neighbour(monster_x as usize, monster_y as usize, dir) {
+                    if digging && self.immovable[x][y].type_object == ImmovableType::Wall {
+                        self.immovable[x][y].type_object = ImmovableType::Background;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The cell next to (`x`, `y`) in direction `dir`, if it is on the map.
+    fn neighbour(&self, x: usize, y: usize, dir: Direction) -> Option<(usize, usize)> {
+        match dir {
+            Direction::Up if x > 0 => Some((x - 1, y)),
+            Direction::Down if x + 1 < self.num_lines => Some((x + 1, y)),
+            Direction::Left if y > 0 => Some((x, y - 1)),
+            Direction::Right if y + 1 < self.line_len => Some((x, y + 1)),
+            _ => None,
+        }
+    }
+
+    /// The reverse of `toward` if the monster can go there, else any other way that is not toward the player.
+    fn flee_direction(&self, x: usize, y: usize, toward: Direction, digging: bool) -> Direction {
+        let choices = match toward {
+            Direction::Up | Direction::Down => [toward.reverse(), Direction::Left, Direction::Right],
+            _ => [toward.reverse(), Direction::Up, Direction::Down],
+        };
+        for dir in choices.iter() {
+            if let Some((nx, ny)) = self.neighbour(x, y, *dir) {
+                if World::passable(&self.immovable[nx][ny], digging) {
+                    return *dir;
+                }
+            }
+        }
+        Direction::None
+    }
+
+    /// Starts a bag wobbling as soon as the cell underneath it is dug out, from any side.
+    fn activate_bags(&mut self) {
+        for x in 0..self.num_lines - 1 {
+            for y in 0..self.line_len {
+                if self.immovable[x][y].type_object == ImmovableType::Bag
+                && self.immovable[x + 1][y].type_object == ImmovableType::Background {
+                    self.immovable[x][y].type_object = ImmovableType::Background;
+                    // Whoever is closest when the bag comes loose is credited with what it crushes.
+                    let owner = self.nearest_player(x, y);
+                    self.movable.push(Movable{
+                        type_object: MovableType::FallingBag,
+                        x: Coord::at(x),
+                        y: Coord::at(y),
+                        dir: Direction::None,
+                        activation_time: self.tick,
+                        previous_dir: Direction::None,
+                        owner,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Lets wobbling bags fall. From then on `activation_time` is the tick the fall began.
+    fn drop_bags(&mut self) {
+        for i in 0..self.movable.len() {
+            if self.movable[i].type_object == MovableType::FallingBag
+            && self.movable[i].dir == Direction::None
+            && self.tick - self.movable[i].activation_time >= TIME_TO_FALL {
+                self.movable[i].dir = Direction::Down;
+                self.movable[i].activation_time = self.tick;
+            }
+        }
+    }
+
+    fn check_for_colision(&mut self) -> Result<(), RuntimeError> {
+
+            let input = self.movable.clone();
+            let bonus = self.is_bonus_active();
+            let mut output = Vec::<Movable>::new();
+            let mut to_remove = Vec::<usize>::new();
+            let mut players_hit = Vec::<usize>::new();
+
+            for i in 0..input.len(){
+                match input[i].type_object {
+                    MovableType::Player => {
+                        let player = input[i].owner;
+                        if !self.is_active(player) {
+                            output.push(input[i]);
+                            continue;
+                        }
+                        let invulnerable = self.is_invulnerable(player);
+                        for j in 0..input.len() {
+                            match input[j].type_object {
+                                MovableType::Monster | MovableType::Hobbin if !invulnerable && !bonus && overlaps(input[i], input[j]) => {
+                                    players_hit.push(player);
+                                }
+                                MovableType::FallingBag if !invulnerable && input[j].dir == Direction::Down && overlaps(input[i], input[j]) => {
+                                    players_hit.push(player);
+                                }
+                                MovableType::Crashed if !to_remove.contains(&j) && overlaps(input[i], input[j]) => {
+                                    self.score(player, ScoreEvent::Gold);
+                                    if self.gold.recharges_weapon {
+                                        self.players[player].shots += 1;
+                                    }
+                                    to_remove.push(j);
+                                },
+                                _ => {},
+                            }
+                        }
+                        output.push(input[i]);
+                    }
+                    MovableType::Monster | MovableType::Hobbin => {
+                        let mut monster_dead = false;
+                        for j in 0..input.len() {
+                            if (input[j].type_object == MovableType::Shot
+                            || (input[j].type_object == MovableType::FallingBag && input[j].dir == Direction::Down))
+                            && overlaps(input[i], input[j]) {
+                                self.score(input[j].owner, if input[j].type_object == MovableType::Shot { ScoreEvent::MonsterShot } else { ScoreEvent::MonsterCrushed });
+                                self.current_number_monsters -= 1;
+                                self.kills += 1;
+                                monster_dead = true;
+                                // A falling bag keeps going; its own arm decides where it lands.
+                                if input[j].type_object == MovableType::Shot {
+                                    to_remove.push(j);
+                                }
+                                break;
+                            }
+                        }
+                        let eater = input.iter().find(|m| m.type_object == MovableType::Player
+                            && self.is_active(m.owner) && overlaps(**m, input[i]));
+                        if let Some(eater) = eater.filter(|_| !monster_dead && bonus) {
+                            self.score(eater.owner, ScoreEvent::MonsterEaten);
+                            self.current_number_monsters -= 1;
+                            self.kills += 1;
+                            monster_dead = true;
+                        }
+                        if !monster_dead {
+                            output.push(input[i]);
+                        };
+                    }
+                    MovableType::FallingBag => {
+                        if to_remove.contains(&i) {
+                            continue;
+                        }
+                        if input[i].dir == Direction::Down && bag_landed(input[i], &self.immovable) {
+                            let rows = ((self.tick - input[i].activation_time) * BAG_SPEED as u64 / STEPS_PER_CELL as u64) as usize;
+                            if rows < ROWS_TO_BREAK {
+                                self.immovable[input[i].x.cell][input[i].y.cell].type_object = ImmovableType::Bag;
+                                continue;
+                            }
+                            output.push(Movable{
+                                type_object: MovableType::Crashed,
+                                x: input[i].x,
+                                y: input[i].y,
+                                dir: Direction::None,
+                                activation_time: self.tick,
+                                previous_dir: Direction::None,
+                                owner: input[i].owner,
+                            });
+                            continue;
+                        }
+                        output.push(input[i]);
+                    }
+                    MovableType::Shot if !to_remove.contains(&i) && !bag_or_shot_crashed(input[i], &self.immovable) => {
+                        output.push(input[i]);
+                    },
+                    MovableType::Crashed if !to_remove.contains(&i) => {
+                        if self.gold.monsters_eat
+                        && input.iter().any(|m| m.type_object.is_monster() && overlaps(*m, input[i])) {
+                            continue;
+                        }
+                        output.push(input[i]);
+                    }
+                    _ => {}
+                }
+            }
+
+            self.movable = output;
+            players_hit.dedup();
+            for player in players_hit {
+                self.lose_life(player)?;
+            }
+            Ok(())
+    }
+
+    /// Puts the bonus on the spawn point once enough monsters have been killed.
+    fn show_bonus(&mut self) {
+        if self.bonus_after == 0 || self.bonus_shown || self.kills < self.bonus_after {
+            return;
+        }
+        let (x, y) = self.spawns[0];
+        self.immovable[x][y].type_object = ImmovableType::Bonus;
+        self.bonus_shown = true;
+    }
+
+    fn next_monster(&mut self) -> MovableType {
+        let kind = self.monster_pattern[self.monsters_spawned % self.monster_pattern.len()];
+        self.monsters_spawned += 1;
+        kind
+    }
+
+    /// Nobbins that have been around for long enough start digging.
+    fn transform_monsters(&mut self) {
+        if self.hobbin_after == 0 {
+            return;
+        }
+        for object in self.movable.iter_mut() {
+            if object.type_object == MovableType::Monster && self.tick - object.activation_time >= self.hobbin_after {
+                object.type_object = MovableType::Hobbin;
+            }
+        }
+    }
+
+    /// Sends in the next scheduled monster, if the schedule allows one now.
+    fn spawn_monster(&mut self) {
+        let due = self.monsters_spawned == 0 || self.tick - self.last_spawned >= self.schedule.interval;
+        let left = self.schedule.total == 0 || self.monsters_spawned < self.schedule.total;
+        if !due || !left || self.current_number_monsters >= self.schedule.max_concurrent || self.spawns.is_empty() {
+            return;
+        }
+        let spawn = self.pick_spawn();
+        let (x, y) = self.spawns[spawn];
+        let kind = self.next_monster();
+        self.movable.push(Movable{
+            type_object: kind,
+            x: Coord::at(x),
+            y: Coord::at(y),
+            dir: Direction::None,
+            activation_time: self.tick,
+            previous_dir: Direction::None,
+            owner: 0,
+        });
+        self.last_spawned = self.tick;
+        self.current_number_monsters += 1;
+    }
+
+    fn pick_spawn(&mut self) -> usize {
+        match self.schedule.order {
+            SpawnOrder::RoundRobin => {
+                let spawn = self.next_spawn % self.spawns.len();
+                self.next_spawn += 1;
+                spawn
+            },
+            SpawnOrder::Random => (self.random() % self.spawns.len() as u64) as usize,
+        }
+    }
+
+    /// Next number from a xorshift generator seeded with `seed`.
+    fn random(&mut self) -> u64 {
+        if self.rng == 0 {
+            self.rng = (self.seed ^ 0x9E37_79B9_7F4A_7C15).max(1);
+        }
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    /// Monsters the level sends in total, 0 for no limit.
+    pub fn scheduled_monsters(&self) -> usize {
+        self.schedule.total
+    }
+
+    /// True once every monster of a limited schedule has been killed.
+    pub fn all_monsters_killed(&self) -> bool {
+        self.schedule.total > 0 && self.kills >= self.schedule.total
+    }
+}